@@ -0,0 +1,298 @@
+//! Labels every state reachable from the starting position by whether and how quickly the
+//! puzzle can still be taken apart from it, and suggests the next move to get there.
+
+use std::collections::{HashMap, VecDeque};
+
+use crate::{
+    graph::StateGraph,
+    valve::{Move, Valve, ValveState},
+};
+
+/// The fewest moves from every state of a graph to any of the goal states.
+pub struct DistanceMap {
+    distances: HashMap<ValveState, usize>,
+}
+impl DistanceMap {
+    /// Searches breadth-first backward along the moves of the graph from every goal state.
+    pub fn new(graph: &StateGraph, is_goal: impl Fn(&ValveState) -> bool) -> Self {
+        let mut previous: HashMap<ValveState, Vec<ValveState>> = HashMap::new();
+        for (state, edges) in graph.edges.iter() {
+            for (_, next) in edges {
+                previous.entry(*next).or_default().push(*state);
+            }
+        }
+
+        let mut distances: HashMap<ValveState, usize> = graph
+            .states
+            .iter()
+            .filter(|s| is_goal(s))
+            .map(|s| (*s, 0))
+            .collect();
+        let mut queue: VecDeque<_> = distances.keys().copied().collect();
+        while let Some(state) = queue.pop_front() {
+            let distance = distances[&state];
+            for prev in previous.get(&state).into_iter().flatten() {
+                if !distances.contains_key(prev) {
+                    distances.insert(*prev, distance + 1);
+                    queue.push_back(*prev);
+                }
+            }
+        }
+
+        Self { distances }
+    }
+
+    /// The distance from a state to the nearest goal, if it is in the graph and can get to one.
+    pub fn get(&self, state: &ValveState) -> Option<usize> {
+        self.distances.get(state).copied()
+    }
+
+    /// The first possible move that gets a step closer to a goal, if any.
+    pub fn best_move(&self, valve: &Valve) -> Option<Move> {
+        let distance = self.get(&valve.state())?;
+        Move::iter().filter(|m| valve.can_move(m)).find(|muv| {
+            let mut next = valve.clone();
+            next.make_move_unchecked(muv);
+            matches!(self.get(&next.state()), Some(d) if d < distance)
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Reachability {
+    /// On a shortest path from the starting position to a separated state.
+    ShortestPath,
+    /// Not on a shortest path, but the puzzle can still be taken apart from it.
+    CanSeparate,
+    /// The puzzle cannot be taken apart from it, so it only leads back toward the start. Since
+    /// every move can be taken back, this is only the case when it can never come apart, such as
+    /// with a geometry loaded with `--geometry` in which nothing is ever separated.
+    OnlyStart,
+}
+impl Reachability {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Reachability::ShortestPath => "On a shortest path to coming apart",
+            Reachability::CanSeparate => "Can still come apart",
+            Reachability::OnlyStart => "Can only get back to the start",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct StateAnalysis {
+    pub reachability: Reachability,
+    /// The fewest moves from the starting position.
+    pub from_start: usize,
+    /// The fewest moves to a separated state, if there is any way to get to one.
+    pub to_goal: Option<usize>,
+}
+
+pub struct Analysis {
+    /// The states in breadth-first order from the start.
+    order: Vec<ValveState>,
+    states: HashMap<ValveState, StateAnalysis>,
+}
+impl Analysis {
+    pub fn new(graph: &StateGraph) -> Self {
+        // Breadth-first forward from the start, which is the order the states are in
+        let mut from_start = HashMap::from([(graph.start, 0)]);
+        for state in graph.states.iter() {
+            let distance = from_start[state];
+            for (_, next) in graph.edges[state].iter() {
+                from_start.entry(*next).or_insert(distance + 1);
+            }
+        }
+
+        let to_goal = DistanceMap::new(graph, |s| Valve::from(*s).is_separated());
+
+        let shortest = to_goal.get(&graph.start);
+        let states = graph
+            .states
+            .iter()
+            .map(|state| {
+                let from_start = from_start[state];
+                let to_goal = to_goal.get(state);
+                let reachability = match to_goal {
+                    None => Reachability::OnlyStart,
+                    Some(d) if Some(from_start + d) == shortest => Reachability::ShortestPath,
+                    Some(_) => Reachability::CanSeparate,
+                };
+                (
+                    *state,
+                    StateAnalysis {
+                        reachability,
+                        from_start,
+                        to_goal,
+                    },
+                )
+            })
+            .collect();
+
+        Self {
+            order: graph.states.clone(),
+            states,
+        }
+    }
+
+    /// The analysis of a state, unless it could not be reached without first taking the puzzle
+    /// apart.
+    pub fn get(&self, state: &ValveState) -> Option<&StateAnalysis> {
+        self.states.get(state)
+    }
+
+    /// Every state in breadth-first order from the start.
+    pub fn iter(&self) -> impl Iterator<Item = (ValveState, StateAnalysis)> + '_ {
+        self.order.iter().map(|s| (*s, self.states[s]))
+    }
+}
+
+/// What hints lead toward.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Goal {
+    #[default]
+    Separation,
+    Start,
+}
+impl Goal {
+    /// The goal after arriving at a state, which changes to the start once the puzzle is apart
+    /// and back again once it is at the start.
+    pub fn update(self, valve: &Valve) -> Self {
+        if valve.is_separated() {
+            Goal::Start
+        } else if valve.state() == ValveState::default() {
+            Goal::Separation
+        } else {
+            self
+        }
+    }
+}
+
+/// Suggests the next move toward taking the puzzle apart or putting it back together.
+pub struct Hints {
+    to_separation: DistanceMap,
+    to_start: DistanceMap,
+}
+impl Default for Hints {
+    fn default() -> Self {
+        let start = Valve::default();
+        let graph = StateGraph::explore_past_separation(&start);
+        Self {
+            to_separation: DistanceMap::new(&graph, |s| Valve::from(*s).is_separated()),
+            to_start: DistanceMap::new(&graph, |s| *s == start.state()),
+        }
+    }
+}
+impl Hints {
+    pub fn best_move(&self, valve: &Valve, goal: Goal) -> Option<Move> {
+        match goal {
+            Goal::Separation => self.to_separation.best_move(valve),
+            Goal::Start => self.to_start.best_move(valve),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::notation::parse_moves;
+
+    const DISASSEMBLY_SCRIPT: &str = include_str!("../procedures/disassembly.txt");
+
+    #[test]
+    fn disassembly_is_a_shortest_path() {
+        let analysis = Analysis::new(&StateGraph::explore(&Valve::default()));
+        let moves = parse_moves(DISASSEMBLY_SCRIPT).unwrap();
+
+        let mut valve = Valve::default();
+        for (i, muv) in moves.iter().enumerate() {
+            let a = analysis.get(&valve.state()).unwrap();
+            assert_eq!(a.reachability, Reachability::ShortestPath);
+            assert_eq!(a.from_start, i);
+            assert_eq!(a.to_goal, Some(moves.len() - i));
+            valve.make_move(muv).unwrap();
+        }
+        assert_eq!(analysis.get(&valve.state()).unwrap().to_goal, Some(0));
+    }
+
+    #[test]
+    fn states_that_cannot_come_apart_only_get_back_to_the_start() {
+        // Leave out every separated state, as if the puzzle could never come apart
+        let mut graph = StateGraph::explore(&Valve::default());
+        let separated = |s: &ValveState| Valve::from(*s).is_separated();
+        graph.states.retain(|s| !separated(s));
+        graph.edges.retain(|s, _| !separated(s));
+        for edges in graph.edges.values_mut() {
+            edges.retain(|(_, next)| !separated(next));
+        }
+
+        let analysis = Analysis::new(&graph);
+        for (state, a) in analysis.iter() {
+            assert_eq!(a.reachability, Reachability::OnlyStart, "{state}");
+            assert_eq!(a.to_goal, None);
+        }
+    }
+
+    #[test]
+    fn best_moves_never_lead_where_the_goal_cannot_be_reached() {
+        let mut graph = StateGraph::explore(&Valve::default());
+        let is_goal = |s: &ValveState| Valve::from(*s).is_separated();
+        let distances = DistanceMap::new(&graph, is_goal);
+
+        // Find a state whose first possible move is not a best one, and take away the state it
+        // leads to so that it has no distance
+        let (valve, unreachable) = graph
+            .states
+            .iter()
+            .map(|s| Valve::from(*s))
+            .find_map(|valve| {
+                let muv = Move::iter().find(|m| valve.can_move(m))?;
+                let mut next = valve.clone();
+                next.make_move_unchecked(&muv);
+                (distances.get(&next.state()) >= distances.get(&valve.state()))
+                    .then(|| (valve, next.state()))
+            })
+            .unwrap();
+        graph.states.retain(|s| *s != unreachable);
+        graph.edges.remove(&unreachable);
+        for edges in graph.edges.values_mut() {
+            edges.retain(|(_, next)| *next != unreachable);
+        }
+
+        let distances = DistanceMap::new(&graph, is_goal);
+        let distance = distances.get(&valve.state()).unwrap();
+        let mut next = valve.clone();
+        next.make_move(&distances.best_move(&valve).unwrap())
+            .unwrap();
+        assert_ne!(next.state(), unreachable);
+        assert!(distances.get(&next.state()).unwrap() < distance);
+    }
+
+    #[test]
+    fn hints_take_the_puzzle_apart_and_back() {
+        let hints = Hints::default();
+        let mut valve = Valve::default();
+
+        let mut goal = Goal::default();
+        let mut moves = 0;
+        while goal == Goal::Separation {
+            valve
+                .make_move(&hints.best_move(&valve, goal).unwrap())
+                .unwrap();
+            goal = goal.update(&valve);
+            moves += 1;
+        }
+        assert!(valve.is_separated());
+        assert_eq!(moves, parse_moves(DISASSEMBLY_SCRIPT).unwrap().len());
+
+        while goal == Goal::Start {
+            valve
+                .make_move(&hints.best_move(&valve, goal).unwrap())
+                .unwrap();
+            goal = goal.update(&valve);
+            moves -= 1;
+        }
+        assert_eq!(valve.state(), ValveState::default());
+        assert_eq!(moves, 0);
+    }
+}
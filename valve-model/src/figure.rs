@@ -0,0 +1,325 @@
+//! Draws the model straight to image files or TikZ pictures for the guide, with the same
+//! layout and colors as the curses display.
+
+use clap::ValueEnum;
+use easycurses::Color;
+use std::{
+    collections::{BTreeSet, HashMap},
+    fs::File,
+    io::BufWriter,
+    io::Write,
+    path::Path,
+};
+use thiserror::Error;
+
+use crate::{
+    layout::{Cell, BACKGROUND_COLOR, VALVE_COLUMNS},
+    valve::Piece,
+};
+
+/// The size of a character cell in pixels, which is about the shape of a terminal cell.
+const CELL_WIDTH: i32 = 18;
+const CELL_HEIGHT: i32 = 36;
+/// The number of background cells left around the pieces when cropping.
+const MARGIN: i32 = 1;
+/// Each pixel of a glyph bitmap is drawn as a square of this many pixels.
+const GLYPH_SCALE: i32 = 3;
+/// The color and width in pixels of the outline around pieces that have just moved.
+const MARK_COLOR: Color = Color::Red;
+const MARK_WIDTH: i32 = 3;
+/// The width of a cell in TikZ pictures in centimeters, which are scaled to fit by the guide.
+const TIKZ_CELL_WIDTH: f32 = 0.25;
+
+#[derive(Error, Debug)]
+pub enum FigureError {
+    #[error("could not write the figure")]
+    Io(#[from] std::io::Error),
+    #[error("could not encode the figure")]
+    Png(#[from] png::EncodingError),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Format {
+    Png,
+    Svg,
+    /// A `tikzpicture` to be included in a LaTeX document with `\input`.
+    Tikz,
+}
+impl Format {
+    pub fn extension(&self) -> &'static str {
+        match self {
+            Format::Png => "png",
+            Format::Svg => "svg",
+            Format::Tikz => "tex",
+        }
+    }
+}
+
+/// The color of the default GNOME Terminal palette that the screenshots were taken with.
+fn rgb(color: Color) -> [u8; 3] {
+    match color {
+        Color::Black => [0x17, 0x14, 0x21],
+        Color::Red => [0xc0, 0x1c, 0x28],
+        Color::Green => [0x26, 0xa2, 0x69],
+        Color::Yellow => [0xa2, 0x73, 0x4c],
+        Color::Blue => [0x12, 0x48, 0x8b],
+        Color::Magenta => [0xa3, 0x47, 0xba],
+        Color::Cyan => [0x2a, 0xa1, 0xb3],
+        Color::White => [0xd0, 0xcf, 0xcc],
+    }
+}
+
+/// A 5 by 7 bitmap of the glyphs used by the pieces, one row per byte with the leftmost
+/// pixel in the highest of the five bits.
+fn glyph_bitmap(glyph: char) -> Option<[u8; 7]> {
+    Some(match glyph {
+        'H' => [0x11, 0x11, 0x11, 0x1f, 0x11, 0x11, 0x11],
+        'V' => [0x11, 0x11, 0x11, 0x11, 0x11, 0x0a, 0x04],
+        'I' => [0x0e, 0x04, 0x04, 0x04, 0x04, 0x04, 0x0e],
+        'O' => [0x0e, 0x11, 0x11, 0x11, 0x11, 0x11, 0x0e],
+        _ => return None,
+    })
+}
+
+/// The range of cell columns and rows of a figure, including the margin, which spans every
+/// column of the sections so that all figures are drawn at the same scale but only the rows
+/// covered by the cells.
+fn bounds(cells: &[Cell]) -> (i32, i32, i32, i32) {
+    let min_x = VALVE_COLUMNS.start() - MARGIN;
+    let max_x = VALVE_COLUMNS.end() + MARGIN;
+    let min_y = cells.iter().map(|c| c.position.y).min().unwrap_or(0) - MARGIN;
+    let max_y = cells.iter().map(|c| c.position.y).max().unwrap_or(0) + MARGIN;
+    (min_x, min_y, max_x - min_x + 1, max_y - min_y + 1)
+}
+
+/// The edges of the outline around the cells shown for the marked pieces, as pairs of corners
+/// counted in cells from the top left of the figure.
+fn marked_edges(cells: &[Cell], marked: &[Piece]) -> Vec<[(i32, i32); 2]> {
+    let (min_x, min_y, _, _) = bounds(cells);
+
+    // Later cells are drawn over earlier ones
+    let shown: HashMap<_, _> = cells
+        .iter()
+        .map(|c| ((c.position.x - min_x, c.position.y - min_y), c.piece))
+        .collect();
+    let region: BTreeSet<_> = shown
+        .into_iter()
+        .filter(|(_, piece)| marked.contains(piece))
+        .map(|(position, _)| position)
+        .collect();
+
+    let mut edges = Vec::new();
+    for &(x, y) in region.iter() {
+        if !region.contains(&(x, y - 1)) {
+            edges.push([(x, y), (x + 1, y)]);
+        }
+        if !region.contains(&(x, y + 1)) {
+            edges.push([(x, y + 1), (x + 1, y + 1)]);
+        }
+        if !region.contains(&(x - 1, y)) {
+            edges.push([(x, y), (x, y + 1)]);
+        }
+        if !region.contains(&(x + 1, y)) {
+            edges.push([(x + 1, y), (x + 1, y + 1)]);
+        }
+    }
+    edges
+}
+
+/// Writes the cells to a file, cropped to the rows they cover, with the marked pieces outlined.
+pub fn write_figure(
+    path: &Path,
+    cells: &[Cell],
+    marked: &[Piece],
+    format: Format,
+) -> Result<(), FigureError> {
+    let mut writer = BufWriter::new(File::create(path)?);
+    match format {
+        Format::Png => write_png(&mut writer, cells, marked)?,
+        Format::Svg => write_svg(&mut writer, cells, marked)?,
+        Format::Tikz => write_tikz(&mut writer, cells, marked)?,
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+pub fn write_svg(writer: &mut impl Write, cells: &[Cell], marked: &[Piece]) -> std::io::Result<()> {
+    let hex = |color| {
+        let [r, g, b] = rgb(color);
+        format!("#{r:02x}{g:02x}{b:02x}")
+    };
+    let (min_x, min_y, columns, rows) = bounds(cells);
+    let (width, height) = (columns * CELL_WIDTH, rows * CELL_HEIGHT);
+
+    writeln!(
+        writer,
+        "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{width}\" height=\"{height}\" \
+         viewBox=\"0 0 {width} {height}\">"
+    )?;
+    writeln!(
+        writer,
+        "  <rect width=\"{width}\" height=\"{height}\" fill=\"{}\"/>",
+        hex(BACKGROUND_COLOR)
+    )?;
+    writeln!(
+        writer,
+        "  <g font-family=\"monospace\" font-size=\"{}\" text-anchor=\"middle\" \
+         dominant-baseline=\"central\">",
+        CELL_HEIGHT * 2 / 3
+    )?;
+
+    for cell in cells {
+        let x = (cell.position.x - min_x) * CELL_WIDTH;
+        let y = (cell.position.y - min_y) * CELL_HEIGHT;
+        writeln!(
+            writer,
+            "    <rect x=\"{x}\" y=\"{y}\" width=\"{CELL_WIDTH}\" height=\"{CELL_HEIGHT}\" fill=\"{}\"/>",
+            hex(cell.background)
+        )?;
+        if cell.glyph != ' ' {
+            writeln!(
+                writer,
+                "    <text x=\"{}\" y=\"{}\" fill=\"{}\">{}</text>",
+                x + CELL_WIDTH / 2,
+                y + CELL_HEIGHT / 2,
+                hex(cell.foreground),
+                cell.glyph
+            )?;
+        }
+    }
+
+    writeln!(writer, "  </g>")?;
+
+    for [(x0, y0), (x1, y1)] in marked_edges(cells, marked) {
+        writeln!(
+            writer,
+            "  <line x1=\"{}\" y1=\"{}\" x2=\"{}\" y2=\"{}\" stroke=\"{}\" stroke-width=\"{MARK_WIDTH}\" \
+             stroke-linecap=\"square\"/>",
+            x0 * CELL_WIDTH,
+            y0 * CELL_HEIGHT,
+            x1 * CELL_WIDTH,
+            y1 * CELL_HEIGHT,
+            hex(MARK_COLOR)
+        )?;
+    }
+
+    writeln!(writer, "</svg>")
+}
+
+pub fn write_png(
+    writer: &mut impl Write,
+    cells: &[Cell],
+    marked: &[Piece],
+) -> Result<(), png::EncodingError> {
+    let (min_x, min_y, columns, rows) = bounds(cells);
+    let (width, height) = (columns * CELL_WIDTH, rows * CELL_HEIGHT);
+
+    let mut pixels = rgb(BACKGROUND_COLOR).repeat(usize::try_from(width * height).unwrap());
+    let mut fill = |x: i32, y: i32, w: i32, h: i32, color: Color| {
+        for py in y..y + h {
+            for px in x..x + w {
+                let i = usize::try_from((py * width + px) * 3).unwrap();
+                pixels[i..i + 3].copy_from_slice(&rgb(color));
+            }
+        }
+    };
+
+    for cell in cells {
+        let x = (cell.position.x - min_x) * CELL_WIDTH;
+        let y = (cell.position.y - min_y) * CELL_HEIGHT;
+        fill(x, y, CELL_WIDTH, CELL_HEIGHT, cell.background);
+
+        // Center the glyph in the cell
+        if let Some(bitmap) = glyph_bitmap(cell.glyph) {
+            let left = x + (CELL_WIDTH - 5 * GLYPH_SCALE) / 2;
+            let top = y + (CELL_HEIGHT - 7 * GLYPH_SCALE) / 2;
+            for (row, bits) in (0..).zip(bitmap) {
+                for column in (0..5).filter(|c| bits & (0x10 >> c) != 0) {
+                    fill(
+                        left + column * GLYPH_SCALE,
+                        top + row * GLYPH_SCALE,
+                        GLYPH_SCALE,
+                        GLYPH_SCALE,
+                        cell.foreground,
+                    );
+                }
+            }
+        }
+    }
+
+    // Edges are drawn centered on the boundary between cells
+    for [(x0, y0), (x1, y1)] in marked_edges(cells, marked) {
+        fill(
+            x0 * CELL_WIDTH - MARK_WIDTH / 2,
+            y0 * CELL_HEIGHT - MARK_WIDTH / 2,
+            (x1 - x0) * CELL_WIDTH + MARK_WIDTH,
+            (y1 - y0) * CELL_HEIGHT + MARK_WIDTH,
+            MARK_COLOR,
+        );
+    }
+
+    let mut encoder = png::Encoder::new(
+        writer,
+        u32::try_from(width).unwrap(),
+        u32::try_from(height).unwrap(),
+    );
+    encoder.set_color(png::ColorType::Rgb);
+    encoder.set_depth(png::BitDepth::Eight);
+    encoder.write_header()?.write_image_data(&pixels)
+}
+
+/// Writes a `tikzpicture` that needs only the `tikz` package, one unit of which is a cell.
+pub fn write_tikz(
+    writer: &mut impl Write,
+    cells: &[Cell],
+    marked: &[Piece],
+) -> std::io::Result<()> {
+    let color = |color| {
+        let [r, g, b] = rgb(color);
+        format!("{{rgb,255:red,{r};green,{g};blue,{b}}}")
+    };
+    let (min_x, min_y, columns, rows) = bounds(cells);
+
+    // Rows go down the page like in the curses display
+    writeln!(writer, "% Drawn by valve-model")?;
+    writeln!(
+        writer,
+        "\\begin{{tikzpicture}}[x={TIKZ_CELL_WIDTH}cm, y=-{}cm]",
+        TIKZ_CELL_WIDTH * (CELL_HEIGHT / CELL_WIDTH) as f32
+    )?;
+    writeln!(
+        writer,
+        "  \\fill[fill={}] (0, 0) rectangle ({columns}, {rows});",
+        color(BACKGROUND_COLOR)
+    )?;
+
+    for cell in cells {
+        let x = cell.position.x - min_x;
+        let y = cell.position.y - min_y;
+        writeln!(
+            writer,
+            "  \\fill[fill={}] ({x}, {y}) rectangle +(1, 1);",
+            color(cell.background)
+        )?;
+        if cell.glyph != ' ' {
+            writeln!(
+                writer,
+                "  \\node[text={}, font=\\ttfamily] at ({}, {}) {{{}}};",
+                color(cell.foreground),
+                x as f32 + 0.5,
+                y as f32 + 0.5,
+                cell.glyph
+            )?;
+        }
+    }
+
+    for [(x0, y0), (x1, y1)] in marked_edges(cells, marked) {
+        writeln!(
+            writer,
+            "  \\draw[draw={}, line width={MARK_WIDTH}pt, line cap=rect] ({x0}, {y0}) -- ({x1}, {y1});",
+            color(MARK_COLOR)
+        )?;
+    }
+
+    writeln!(writer, "\\end{{tikzpicture}}")
+}
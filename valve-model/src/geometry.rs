@@ -0,0 +1,251 @@
+use euclid::Point2D;
+use serde::Deserialize;
+use std::{collections::HashSet, path::Path, sync::OnceLock};
+use thiserror::Error;
+
+use crate::valve::{
+    InnerRing, Inside, InsidePoint, ModPoint, OuterRing, Outside, OutsidePoint, Ring,
+    VALVE_SECTION_HEIGHT, VALVE_SECTION_WIDTH,
+};
+
+/// The geometry file built into the executable, used unless another is loaded at startup.
+const DEFAULT_GEOMETRY: &str = include_str!("../geometry.toml");
+
+static GEOMETRY: OnceLock<Geometry> = OnceLock::new();
+
+#[derive(Error, Debug)]
+pub enum GeometryError {
+    #[error("could not read the geometry file")]
+    Io(#[from] std::io::Error),
+    #[error("could not parse the geometry file")]
+    Parse(#[from] toml::de::Error),
+    #[error("the {piece} has no points")]
+    Empty { piece: &'static str },
+    #[error("the {piece} height {height} is not from 1 to {VALVE_SECTION_HEIGHT}")]
+    InvalidHeight { piece: &'static str, height: i32 },
+    #[error("the {piece} point ({x}, {y}) is outside of the piece")]
+    OutOfBounds { piece: &'static str, x: i32, y: i32 },
+    #[error("the {piece} point ({x}, {y}) is listed more than once")]
+    Duplicate { piece: &'static str, x: i32, y: i32 },
+    #[error("the {piece} and {other} overlap at ({x}, {y}) in the starting position")]
+    Overlap {
+        piece: &'static str,
+        other: &'static str,
+        x: i32,
+        y: i32,
+    },
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct HalfFile {
+    height: i32,
+    inside: Vec<[i32; 2]>,
+    outside: Vec<[i32; 2]>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RingFile {
+    height: i32,
+    points: Vec<[i32; 2]>,
+    start: [i32; 2],
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct GeometryFile {
+    hanayama_half: HalfFile,
+    valve_half: HalfFile,
+    inner_ring: RingFile,
+    outer_ring: RingFile,
+}
+
+pub struct HalfGeometry {
+    /// The number of layers that the half spans.
+    pub height: i32,
+    pub inside: HashSet<InsidePoint>,
+    pub outside: HashSet<OutsidePoint>,
+}
+
+pub struct RingGeometry<R: Ring> {
+    /// The number of layers that the ring spans from its top.
+    pub height: i32,
+    /// Relative to the position of the ring.
+    pub points: Vec<ModPoint<R>>,
+    pub start: ModPoint<R::Coordinates>,
+}
+
+/// The extrusions of all the pieces along with the starting positions of the rings.
+pub struct Geometry {
+    pub hanayama_half: HalfGeometry,
+    /// With the Valve half level with the Hanayama half.
+    pub valve_half: HalfGeometry,
+    pub inner_ring: RingGeometry<InnerRing>,
+    pub outer_ring: RingGeometry<OuterRing>,
+}
+impl Geometry {
+    /// Returns the installed geometry, installing the built-in one if none has been.
+    pub fn get() -> &'static Self {
+        GEOMETRY.get_or_init(|| Self::parse(DEFAULT_GEOMETRY).expect("built-in geometry is valid"))
+    }
+
+    /// Makes this the geometry used by the model, which must happen before it is first used.
+    pub fn install(self) {
+        if GEOMETRY.set(self).is_err() {
+            panic!("the geometry was already in use when installing another");
+        }
+    }
+
+    pub fn load(path: &Path) -> Result<Self, GeometryError> {
+        Self::parse(&std::fs::read_to_string(path)?)
+    }
+
+    pub fn parse(s: &str) -> Result<Self, GeometryError> {
+        let file: GeometryFile = toml::from_str(s)?;
+
+        let geometry = Self {
+            hanayama_half: half_geometry("Hanayama half", &file.hanayama_half)?,
+            valve_half: half_geometry("Valve half", &file.valve_half)?,
+            inner_ring: ring_geometry("inner ring", &file.inner_ring)?,
+            outer_ring: ring_geometry("outer ring", &file.outer_ring)?,
+        };
+        geometry.check_overlaps()?;
+
+        Ok(geometry)
+    }
+
+    /// Ensures that none of the pieces overlap each other in the starting position.
+    fn check_overlaps(&self) -> Result<(), GeometryError> {
+        fn check<U>(
+            pieces: [(&'static str, HashSet<Point2D<i32, U>>); 3],
+        ) -> Result<(), GeometryError> {
+            for (i, (piece, points)) in pieces.iter().enumerate() {
+                for (other, other_points) in pieces.iter().skip(i + 1) {
+                    if let Some(p) = points.intersection(other_points).next() {
+                        return Err(GeometryError::Overlap {
+                            piece,
+                            other,
+                            x: p.x,
+                            y: p.y,
+                        });
+                    }
+                }
+            }
+            Ok(())
+        }
+
+        check::<Inside>([
+            ("Hanayama half", self.hanayama_half.inside.clone()),
+            ("Valve half", self.valve_half.inside.clone()),
+            ("inner ring", ring_points(&self.inner_ring)),
+        ])?;
+        check::<Outside>([
+            ("Hanayama half", self.hanayama_half.outside.clone()),
+            ("Valve half", self.valve_half.outside.clone()),
+            ("outer ring", ring_points(&self.outer_ring)),
+        ])
+    }
+}
+
+/// The points of a ring in its starting position.
+fn ring_points<R: Ring>(ring: &RingGeometry<R>) -> HashSet<Point2D<i32, R::Coordinates>> {
+    ring.points
+        .iter()
+        .map(|p| (ring.start + p.cast_unit()).into())
+        .collect()
+}
+
+fn height(piece: &'static str, height: i32) -> Result<i32, GeometryError> {
+    if !(1..=VALVE_SECTION_HEIGHT).contains(&height) {
+        return Err(GeometryError::InvalidHeight { piece, height });
+    }
+    Ok(height)
+}
+
+fn half_geometry(piece: &'static str, half: &HalfFile) -> Result<HalfGeometry, GeometryError> {
+    let height = height(piece, half.height)?;
+    Ok(HalfGeometry {
+        height,
+        inside: half_points(piece, height, &half.inside)?,
+        outside: half_points(piece, height, &half.outside)?,
+    })
+}
+
+fn half_points<U>(
+    piece: &'static str,
+    height: i32,
+    points: &[[i32; 2]],
+) -> Result<HashSet<Point2D<i32, U>>, GeometryError> {
+    let mut set = HashSet::new();
+
+    for &[x, y] in points {
+        if !(0..VALVE_SECTION_WIDTH).contains(&x) || !(0..height).contains(&y) {
+            return Err(GeometryError::OutOfBounds { piece, x, y });
+        }
+        if !set.insert(Point2D::new(x, y)) {
+            return Err(GeometryError::Duplicate { piece, x, y });
+        }
+    }
+
+    if set.is_empty() {
+        return Err(GeometryError::Empty { piece });
+    }
+    Ok(set)
+}
+
+fn ring_geometry<R: Ring>(
+    piece: &'static str,
+    ring: &RingFile,
+) -> Result<RingGeometry<R>, GeometryError> {
+    let height = height(piece, ring.height)?;
+    let mut points = Vec::new();
+
+    for &[x, y] in ring.points.iter() {
+        // The top of a ring is the layer above its position
+        if !(0..VALVE_SECTION_WIDTH).contains(&x) || !(-1..height - 1).contains(&y) {
+            return Err(GeometryError::OutOfBounds { piece, x, y });
+        }
+        let point = ModPoint::new(x, y);
+        if points.contains(&point) {
+            return Err(GeometryError::Duplicate { piece, x, y });
+        }
+        points.push(point);
+    }
+
+    if points.is_empty() {
+        return Err(GeometryError::Empty { piece });
+    }
+    Ok(RingGeometry {
+        height,
+        points,
+        start: ModPoint::new(ring.start[0], ring.start[1]),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn points_must_be_within_the_height() {
+        let shorter = DEFAULT_GEOMETRY.replacen("height = 5", "height = 4", 1);
+        assert!(matches!(
+            Geometry::parse(&shorter),
+            Err(GeometryError::OutOfBounds {
+                piece: "Hanayama half",
+                x: 0,
+                y: 4
+            })
+        ));
+
+        let taller = DEFAULT_GEOMETRY.replacen("height = 5", "height = 6", 1);
+        assert!(matches!(
+            Geometry::parse(&taller),
+            Err(GeometryError::InvalidHeight {
+                piece: "Hanayama half",
+                height: 6
+            })
+        ));
+    }
+}
@@ -0,0 +1,147 @@
+use bare_metal_modulo::MNum;
+use std::{
+    collections::{HashMap, HashSet, VecDeque},
+    io::Write,
+};
+
+use crate::valve::{Move, Valve, ValveState, ViewingSide};
+
+/// Every state reachable from a starting state, along with the legal moves between them.
+pub struct StateGraph {
+    pub start: ValveState,
+    /// The states in breadth-first order, so that the start state comes first.
+    pub states: Vec<ValveState>,
+    /// The legal moves out of each state and the states at which they arrive.
+    pub edges: HashMap<ValveState, Vec<(Move, ValveState)>>,
+}
+impl StateGraph {
+    /// Explores every state reachable without moving on from a separated state.
+    pub fn explore(start: &Valve) -> Self {
+        Self::explore_until(start, Valve::is_separated)
+    }
+
+    /// Explores every state reachable, including by moving the pieces after they come apart.
+    pub fn explore_past_separation(start: &Valve) -> Self {
+        Self::explore_until(start, |_| false)
+    }
+
+    /// Explores every state reachable without moving on from a terminal state.
+    fn explore_until(start: &Valve, is_terminal: impl Fn(&Valve) -> bool) -> Self {
+        let mut states = vec![start.state()];
+        let mut seen = HashSet::from([start.state()]);
+        let mut edges = HashMap::new();
+        let mut queue = VecDeque::from([start.state()]);
+
+        while let Some(state) = queue.pop_front() {
+            let valve = Valve::from(state);
+            let mut out = Vec::new();
+
+            let moves = Move::iter().filter(|m| !is_terminal(&valve) && valve.can_move(m));
+            for muv in moves {
+                let mut next = valve.clone();
+                next.make_move_unchecked(&muv);
+
+                let next = next.state();
+                if seen.insert(next) {
+                    states.push(next);
+                    queue.push_back(next);
+                }
+                out.push((muv, next));
+            }
+
+            edges.insert(state, out);
+        }
+
+        Self {
+            start: start.state(),
+            states,
+            edges,
+        }
+    }
+
+    /// Writes the graph in GraphViz DOT format.
+    ///
+    /// Moves whose inverse leads straight back are merged into a single undirected edge,
+    /// while the start state and separated states are highlighted. Ring moves are labelled as
+    /// rotations seen from a side if one is given.
+    pub fn write_dot(
+        &self,
+        writer: &mut impl Write,
+        names: Option<ViewingSide>,
+    ) -> std::io::Result<()> {
+        let indices: HashMap<_, _> = self
+            .states
+            .iter()
+            .enumerate()
+            .map(|(i, s)| (*s, i))
+            .collect();
+
+        writeln!(writer, "digraph valve {{")?;
+        writeln!(writer, "    node [shape=box, fontname=monospace];")?;
+        writeln!(writer, "    edge [fontname=monospace];")?;
+
+        for (i, state) in self.states.iter().enumerate() {
+            let style = if *state == self.start {
+                ", style=filled, fillcolor=palegreen"
+            } else if Valve::from(*state).is_separated() {
+                ", style=filled, fillcolor=lightcoral"
+            } else {
+                ""
+            };
+
+            writeln!(
+                writer,
+                "    s{i} [label=\"I: ({}, {})\\nO: ({}, {})\\nV: {}\"{style}];",
+                state.inner_ring.x.a(),
+                state.inner_ring.y,
+                state.outer_ring.x.a(),
+                state.outer_ring.y,
+                state.valve_half_y,
+            )?;
+        }
+
+        for (i, state) in self.states.iter().enumerate() {
+            for (muv, next) in self.edges[state].iter() {
+                let j = indices[next];
+                let reversible = self.edges[next]
+                    .iter()
+                    .any(|(m, s)| s == state && *m == -muv.clone());
+
+                if !reversible {
+                    writeln!(writer, "    s{i} -> s{j} [label=\"{}\"];", muv.name(names))?;
+                } else if i < j {
+                    writeln!(
+                        writer,
+                        "    s{i} -> s{j} [label=\"{}\", dir=none];",
+                        muv.name(names)
+                    )?;
+                }
+            }
+        }
+
+        writeln!(writer, "}}")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::procedures::Guide;
+
+    #[test]
+    fn separated_states_are_terminal_only_when_exploring() {
+        let graph = StateGraph::explore(&Valve::default());
+        for state in graph.states.iter() {
+            let valve = Valve::from(*state);
+            if valve.is_separated() {
+                assert!(graph.edges[state].is_empty(), "{state} has moves");
+                assert!(Move::iter().any(|m| valve.can_move(&m)), "{state} is stuck");
+            }
+        }
+
+        // The reassembly starts from where the disassembly comes apart
+        let apart = Valve::from(ValveState::from(Guide::get().reassembly.start));
+        assert!(apart.is_separated());
+        assert!(graph.states.contains(&apart.state()));
+    }
+}
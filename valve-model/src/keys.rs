@@ -0,0 +1,284 @@
+//! Key bindings for the curses interface, which start from a preset and can be changed in a
+//! TOML file.
+
+use clap::ValueEnum;
+use easycurses::Input;
+use itertools::Itertools;
+use serde::Deserialize;
+use std::{collections::HashMap, path::Path};
+use strum::IntoEnumIterator;
+use strum_macros::EnumIter;
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+pub enum KeysError {
+    #[error("could not read the key bindings file")]
+    Io(#[from] std::io::Error),
+    #[error("could not parse the key bindings file")]
+    Parse(#[from] toml::de::Error),
+    #[error("`{0}` is not a key")]
+    UnknownKey(String),
+    #[error("{key} is bound to both `{first}` and `{second}`")]
+    Conflict {
+        key: String,
+        first: &'static str,
+        second: &'static str,
+    },
+}
+
+/// Everything that can be done with a key outside of prompts and menus.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, EnumIter, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Action {
+    RotateLeft,
+    RotateRight,
+    RotateLeftUntilStop,
+    RotateRightUntilStop,
+    MoveUp,
+    MoveDown,
+    ChangeRing,
+    ToggleGravity,
+    ChangeVerticalPreference,
+    EnterMoves,
+    SaveSession,
+    Hint,
+    Undo,
+    Redo,
+    Reset,
+    Quit,
+}
+impl Action {
+    /// The name used in key binding files.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Action::RotateLeft => "rotate_left",
+            Action::RotateRight => "rotate_right",
+            Action::RotateLeftUntilStop => "rotate_left_until_stop",
+            Action::RotateRightUntilStop => "rotate_right_until_stop",
+            Action::MoveUp => "move_up",
+            Action::MoveDown => "move_down",
+            Action::ChangeRing => "change_ring",
+            Action::ToggleGravity => "toggle_gravity",
+            Action::ChangeVerticalPreference => "change_vertical_preference",
+            Action::EnterMoves => "enter_moves",
+            Action::SaveSession => "save_session",
+            Action::Hint => "hint",
+            Action::Undo => "undo",
+            Action::Redo => "redo",
+            Action::Reset => "reset",
+            Action::Quit => "quit",
+        }
+    }
+}
+
+/// The lines of help, each describing actions that are shown together.
+const HELP: [(&str, &[Action]); 13] = [
+    (
+        "Move current ring",
+        &[Action::RotateLeft, Action::RotateRight],
+    ),
+    (
+        "Move current ring until it stops",
+        &[Action::RotateLeftUntilStop, Action::RotateRightUntilStop],
+    ),
+    ("Move valve half", &[Action::MoveUp, Action::MoveDown]),
+    ("Change current ring", &[Action::ChangeRing]),
+    ("Toggle gravity", &[Action::ToggleGravity]),
+    (
+        "Change which vertical moves to prefer",
+        &[Action::ChangeVerticalPreference],
+    ),
+    ("Enter moves", &[Action::EnterMoves]),
+    ("Save session", &[Action::SaveSession]),
+    ("Hint at the next move", &[Action::Hint]),
+    ("Undo", &[Action::Undo]),
+    ("Redo", &[Action::Redo]),
+    ("Reset", &[Action::Reset]),
+    ("Quit", &[Action::Quit]),
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, ValueEnum, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Preset {
+    /// Arrow keys to move, with `-` and `+` to undo and redo.
+    #[default]
+    Arrows,
+    /// `h`, `j`, `k` and `l` to move, with `u` and Ctrl+R to undo and redo and `?` for a hint.
+    Vi,
+    /// `w`, `a`, `s` and `d` to move, with `z` and `x` to undo and redo.
+    Wasd,
+}
+impl Preset {
+    fn bindings(&self) -> Vec<(Action, &'static [&'static str])> {
+        let moves: [&'static [&'static str]; 6] = match self {
+            Preset::Arrows => [
+                &["left"],
+                &["right"],
+                &["shift-left"],
+                &["shift-right"],
+                &["up"],
+                &["down"],
+            ],
+            Preset::Vi => [&["h"], &["l"], &["H"], &["L"], &["k"], &["j"]],
+            Preset::Wasd => [&["a"], &["d"], &["A"], &["D"], &["w"], &["s"]],
+        };
+        let (save, hint, undo, redo): (&'static [&'static str], _, _, _) = match self {
+            Preset::Arrows => (&["s"], &["h"][..], &["-"][..], &["+", "="][..]),
+            Preset::Vi => (&["w"], &["?"][..], &["u"][..], &["ctrl-r"][..]),
+            Preset::Wasd => (&["p"], &["h"][..], &["z"][..], &["x"][..]),
+        };
+
+        vec![
+            (Action::RotateLeft, moves[0]),
+            (Action::RotateRight, moves[1]),
+            (Action::RotateLeftUntilStop, moves[2]),
+            (Action::RotateRightUntilStop, moves[3]),
+            (Action::MoveUp, moves[4]),
+            (Action::MoveDown, moves[5]),
+            (Action::ChangeRing, &["space"]),
+            (Action::ToggleGravity, &["g"]),
+            (Action::ChangeVerticalPreference, &["v"]),
+            (Action::EnterMoves, &[":"]),
+            (Action::SaveSession, save),
+            (Action::Hint, hint),
+            (Action::Undo, undo),
+            (Action::Redo, redo),
+            (Action::Reset, &["r"]),
+            (Action::Quit, &["q"]),
+        ]
+    }
+}
+
+/// A key bindings file, which starts from a preset and replaces the keys of any actions listed.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct KeysFile {
+    preset: Option<Preset>,
+    #[serde(default)]
+    bindings: HashMap<Action, Vec<String>>,
+}
+
+/// Parses a key as either a single character or the name of a special key, such as `left`,
+/// `shift-right`, `space` or `ctrl-r`.
+fn parse_key(name: &str) -> Option<Input> {
+    let mut chars = name.chars();
+    if let (Some(c), None) = (chars.next(), chars.next()) {
+        return Some(Input::Character(c));
+    }
+
+    let name = name.to_ascii_lowercase();
+    Some(match name.as_str() {
+        "left" => Input::KeyLeft,
+        "right" => Input::KeyRight,
+        "up" => Input::KeyUp,
+        "down" => Input::KeyDown,
+        "shift-left" => Input::KeySLeft,
+        "shift-right" => Input::KeySRight,
+        "space" => Input::Character(' '),
+        _ => {
+            let c = name.strip_prefix("ctrl-")?;
+            let mut chars = c.chars();
+            match (chars.next(), chars.next()) {
+                (Some(c @ 'a'..='z'), None) => Input::Character(char::from(c as u8 - b'a' + 1)),
+                _ => return None,
+            }
+        }
+    })
+}
+
+fn key_name(key: Input) -> String {
+    match key {
+        Input::KeyLeft => "Left".into(),
+        Input::KeyRight => "Right".into(),
+        Input::KeyUp => "Up".into(),
+        Input::KeyDown => "Down".into(),
+        Input::KeySLeft => "Shift+Left".into(),
+        Input::KeySRight => "Shift+Right".into(),
+        Input::Character(' ') => "Space".into(),
+        Input::Character(c @ '\u{1}'..='\u{1a}') => {
+            format!("Ctrl+{}", char::from(c as u8 - 1 + b'A'))
+        }
+        Input::Character(c) => c.to_string(),
+        other => format!("{other:?}"),
+    }
+}
+
+pub struct KeyBindings {
+    /// The keys bound to each action in the order that they are listed.
+    keys: HashMap<Action, Vec<Input>>,
+    actions: HashMap<Input, Action>,
+}
+impl KeyBindings {
+    pub fn preset(preset: Preset) -> Self {
+        Self::new(
+            preset
+                .bindings()
+                .into_iter()
+                .map(|(action, keys)| (action, keys.iter().map(|k| k.to_string()).collect()))
+                .collect(),
+        )
+        .expect("presets are valid")
+    }
+
+    /// Loads bindings from a file, starting from its preset or otherwise the given one.
+    pub fn load(path: &Path, preset: Preset) -> Result<Self, KeysError> {
+        let file: KeysFile = toml::from_str(&std::fs::read_to_string(path)?)?;
+
+        let mut bindings: HashMap<_, _> = file
+            .preset
+            .unwrap_or(preset)
+            .bindings()
+            .into_iter()
+            .map(|(action, keys)| (action, keys.iter().map(|k| k.to_string()).collect()))
+            .collect();
+        bindings.extend(file.bindings);
+
+        Self::new(bindings)
+    }
+
+    fn new(bindings: HashMap<Action, Vec<String>>) -> Result<Self, KeysError> {
+        let mut keys = HashMap::new();
+        let mut actions = HashMap::new();
+
+        // In a fixed order so that conflicts are always reported the same way
+        for action in Action::iter() {
+            let mut action_keys = Vec::new();
+            for name in bindings.get(&action).into_iter().flatten() {
+                let key = parse_key(name).ok_or_else(|| KeysError::UnknownKey(name.clone()))?;
+                if let Some(first) = actions.insert(key, action) {
+                    return Err(KeysError::Conflict {
+                        key: key_name(key),
+                        first: first.name(),
+                        second: action.name(),
+                    });
+                }
+                action_keys.push(key);
+            }
+            keys.insert(action, action_keys);
+        }
+
+        Ok(Self { keys, actions })
+    }
+
+    pub fn action(&self, input: Input) -> Option<Action> {
+        self.actions.get(&input).copied()
+    }
+
+    /// The keys bound to an action, such as `+ or =`.
+    pub fn key_names(&self, action: Action) -> String {
+        match self.keys[&action].as_slice() {
+            [] => "(unbound)".to_string(),
+            keys => keys.iter().map(|k| key_name(*k)).join(" or "),
+        }
+    }
+
+    /// Describes every action along with the keys bound to it.
+    pub fn help(&self) -> Vec<String> {
+        HELP.iter()
+            .map(|(description, actions)| {
+                let keys = actions.iter().map(|a| self.key_names(*a)).join(" / ");
+                format!("{description}: {keys}")
+            })
+            .collect()
+    }
+}
@@ -0,0 +1,292 @@
+//! The model laid out as a grid of character cells, which is shared by the curses display
+//! and the figures drawn for the guide.
+
+use easycurses::{Color, ColorPair};
+use euclid::{Point2D, Translation2D};
+use itertools::Itertools;
+use std::{collections::HashSet, f64::consts::PI, ops::RangeInclusive};
+
+use crate::{
+    valve::{
+        Collisions, Inside, InsidePoint, Outside, OutsidePoint, Piece, Ring, Valve,
+        VALVE_MAX_HALF_Y_DISPLACEMENT, VALVE_SECTION_HEIGHT, VALVE_SECTION_WIDTH,
+    },
+    Absolute,
+};
+
+pub const BACKGROUND_COLOR: Color = Color::Blue;
+pub const RING_MAIN_COLOR: Color = Color::Magenta;
+pub const RING_BACKGROUND_COLOR: Color = Color::Yellow;
+pub const HALF_MAIN_COLOR: Color = Color::White;
+pub const HALF_BACKGROUND_COLOR: Color = Color::Black;
+pub const BLOCKED_COLOR: Color = Color::Red;
+pub const HINT_COLOR: Color = Color::Green;
+
+const START_X: i32 = 1;
+const INSIDE_TRANS: Translation2D<i32, Inside, Absolute> =
+    Translation2D::new(START_X + 1, VALVE_MAX_HALF_Y_DISPLACEMENT);
+const OUTSIDE_TRANS: Translation2D<i32, Outside, Absolute> = Translation2D::new(
+    START_X + 1 + VALVE_SECTION_WIDTH + 5,
+    VALVE_MAX_HALF_Y_DISPLACEMENT,
+);
+/// The columns covered by both sections, including the sides of the rings, whatever the state.
+pub const VALVE_COLUMNS: RangeInclusive<i32> =
+    INSIDE_TRANS.x - 1..=OUTSIDE_TRANS.x + VALVE_SECTION_WIDTH;
+const HANAYAMA_HALF_CHAR: char = 'H';
+const VALVE_HALF_CHAR: char = 'V';
+const INNER_RING_CHAR: char = 'I';
+const OUTER_RING_CHAR: char = 'O';
+/// Marks the extrusion at the top of the outer ring, which is the one the guide uses to find the
+/// starting position.
+const KNOWN_EXTRUSION_CHAR: char = '*';
+/// The center of the top-down view, to the right of the outside section and level with the
+/// middle of the rings when they are level with the half rings.
+const TOP_VIEW_CENTER: Point2D<i32, Absolute> = Point2D::new(
+    OUTSIDE_TRANS.x + VALVE_SECTION_WIDTH + 14,
+    VALVE_MAX_HALF_Y_DISPLACEMENT + VALVE_SECTION_HEIGHT / 2,
+);
+/// The distances in rows from the center of the top-down view to the vertices of the hexagons of
+/// the inner ring, the half rings and the outer ring.
+const TOP_VIEW_RADII: [i32; 3] = [2, 4, 6];
+
+/// A single character cell of a piece.
+pub struct Cell {
+    pub position: Point2D<i32, Absolute>,
+    pub piece: Piece,
+    pub glyph: char,
+    pub foreground: Color,
+    pub background: Color,
+}
+impl Cell {
+    pub fn color_pair(&self) -> ColorPair {
+        ColorPair::new(self.foreground, self.background)
+    }
+}
+
+/// The colors of the cells of a piece as foreground and background.
+pub fn piece_colors(piece: Piece) -> (Color, Color) {
+    match piece {
+        Piece::HanayamaHalf => (HALF_MAIN_COLOR, HALF_BACKGROUND_COLOR),
+        Piece::ValveHalf => (HALF_BACKGROUND_COLOR, HALF_MAIN_COLOR),
+        Piece::InnerRing => (RING_MAIN_COLOR, RING_BACKGROUND_COLOR),
+        Piece::OuterRing => (RING_BACKGROUND_COLOR, RING_MAIN_COLOR),
+    }
+}
+
+/// Lays out the inside section on the left and the outside section on the right.
+///
+/// Cells are in drawing order, so where pieces overlap the later cell is the one shown.
+pub fn valve_cells(valve: &Valve) -> Vec<Cell> {
+    let mut cells = Vec::new();
+    let mut add =
+        |piece: Piece, glyph: char, points: &mut dyn Iterator<Item = Point2D<i32, Absolute>>| {
+            let (foreground, background) = piece_colors(piece);
+            cells.extend(points.map(|position| Cell {
+                position,
+                piece,
+                glyph,
+                foreground,
+                background,
+            }));
+        };
+
+    // Inside section
+    add(
+        Piece::HanayamaHalf,
+        HANAYAMA_HALF_CHAR,
+        &mut valve
+            .hanayama_half
+            .inside_points()
+            .iter()
+            .map(|p| INSIDE_TRANS.transform_point(*p)),
+    );
+    add(
+        Piece::ValveHalf,
+        VALVE_HALF_CHAR,
+        &mut valve
+            .valve_half
+            .inside_points(None)
+            .into_iter()
+            .map(|p| INSIDE_TRANS.transform_point(p)),
+    );
+    add(
+        Piece::InnerRing,
+        INNER_RING_CHAR,
+        &mut valve
+            .inner_ring
+            .points(None)
+            .map(|p| INSIDE_TRANS.transform_point(p)),
+    );
+    add(
+        Piece::InnerRing,
+        ' ',
+        &mut [-1, VALVE_SECTION_WIDTH].into_iter().flat_map(|x| {
+            (0..VALVE_SECTION_HEIGHT).map(move |y| {
+                INSIDE_TRANS.transform_point(InsidePoint::new(x, y + valve.inner_ring.top()))
+            })
+        }),
+    );
+
+    // Outside section
+    add(
+        Piece::HanayamaHalf,
+        HANAYAMA_HALF_CHAR,
+        &mut valve
+            .hanayama_half
+            .outside_points()
+            .iter()
+            .map(|p| OUTSIDE_TRANS.transform_point(*p)),
+    );
+    add(
+        Piece::ValveHalf,
+        VALVE_HALF_CHAR,
+        &mut valve
+            .valve_half
+            .outside_points(None)
+            .into_iter()
+            .map(|p| OUTSIDE_TRANS.transform_point(p)),
+    );
+    add(
+        Piece::OuterRing,
+        OUTER_RING_CHAR,
+        &mut valve
+            .outer_ring
+            .points(None)
+            .map(|p| OUTSIDE_TRANS.transform_point(p)),
+    );
+    add(
+        Piece::OuterRing,
+        ' ',
+        &mut [-1, VALVE_SECTION_WIDTH].into_iter().flat_map(|x| {
+            (0..VALVE_SECTION_HEIGHT).map(move |y| {
+                OUTSIDE_TRANS.transform_point(OutsidePoint::new(x, y + valve.outer_ring.top()))
+            })
+        }),
+    );
+
+    cells
+}
+
+/// The positions of the cells along each side of a hexagon in the top-down view, with a vertex
+/// at the top and the sides in clockwise order from the upper right.
+fn hexagon_sides(radius: i32) -> Vec<(usize, Point2D<i32, Absolute>)> {
+    let vertex = |k: i32| {
+        let angle = PI / 2.0 - f64::from(k) * PI / 3.0;
+        // Cells are about twice as tall as they are wide
+        (
+            2.0 * f64::from(radius) * angle.cos(),
+            -f64::from(radius) * angle.sin(),
+        )
+    };
+
+    let mut sides: Vec<(usize, Point2D<i32, Absolute>)> = Vec::new();
+    let steps = 8 * radius;
+    for (segment, k) in (0..VALVE_SECTION_WIDTH).enumerate() {
+        let ((x0, y0), (x1, y1)) = (vertex(k), vertex(k + 1));
+        for i in 0..steps {
+            let t = f64::from(i) / f64::from(steps);
+            let position = TOP_VIEW_CENTER
+                + euclid::vec2(
+                    (x0 + t * (x1 - x0)).round() as i32,
+                    (y0 + t * (y1 - y0)).round() as i32,
+                );
+            if !sides.iter().any(|(_, p)| *p == position) {
+                sides.push((segment, position));
+            }
+        }
+    }
+    sides
+}
+
+/// Lays out the puzzle as seen from above with the HANAYAMA face up, with the hexagons of the
+/// inner ring, the half rings and the outer ring from the center outward.
+///
+/// The Hanayama half is on the right and the segments go clockwise from the upper right, which
+/// is the same as the columns of the inside section and the reverse of the outside section. The
+/// sides of the rings with extrusions show the ring's letter, or a star for the top extrusion of
+/// the outer ring.
+pub fn top_view_cells(valve: &Valve) -> Vec<Cell> {
+    let hanayama_segments = valve
+        .hanayama_half
+        .inside_points()
+        .iter()
+        .map(|p| p.x)
+        .collect::<HashSet<_>>();
+    let inner_segments = valve.inner_ring.points(None).map(|p| p.x).collect_vec();
+    let outer_segments = valve
+        .outer_ring
+        .points(None)
+        .map(|p| VALVE_SECTION_WIDTH - 1 - p.x)
+        .collect_vec();
+    let known_segment = valve
+        .outer_ring
+        .points(None)
+        .min_by_key(|p| p.y)
+        .map(|p| VALVE_SECTION_WIDTH - 1 - p.x);
+
+    let [inner_radius, half_radius, outer_radius] = TOP_VIEW_RADII;
+    let mut cells = Vec::new();
+    let mut add = |radius: i32, glyph: &dyn Fn(i32) -> (Piece, char)| {
+        for (segment, position) in hexagon_sides(radius) {
+            let (piece, glyph) = glyph(i32::try_from(segment).unwrap());
+            let (foreground, background) = piece_colors(piece);
+            cells.push(Cell {
+                position,
+                piece,
+                glyph,
+                foreground,
+                background,
+            });
+        }
+    };
+
+    add(inner_radius, &|segment| {
+        let glyph = if inner_segments.contains(&segment) {
+            INNER_RING_CHAR
+        } else {
+            ' '
+        };
+        (Piece::InnerRing, glyph)
+    });
+    add(half_radius, &|segment| {
+        if hanayama_segments.contains(&segment) {
+            (Piece::HanayamaHalf, HANAYAMA_HALF_CHAR)
+        } else {
+            (Piece::ValveHalf, VALVE_HALF_CHAR)
+        }
+    });
+    add(outer_radius, &|segment| {
+        let glyph = if Some(segment) == known_segment {
+            KNOWN_EXTRUSION_CHAR
+        } else if outer_segments.contains(&segment) {
+            OUTER_RING_CHAR
+        } else {
+            ' '
+        };
+        (Piece::OuterRing, glyph)
+    });
+
+    cells
+}
+
+/// The piece shown at a position, if any.
+pub fn piece_at(valve: &Valve, position: Point2D<i32, Absolute>) -> Option<Piece> {
+    valve_cells(valve)
+        .into_iter()
+        .rev()
+        .find(|c| c.position == position)
+        .map(|c| c.piece)
+}
+
+/// The positions of the cells of both the rings and the half rings that are in the way of a move.
+pub fn collision_positions(collisions: &Collisions) -> Vec<Point2D<i32, Absolute>> {
+    let inside = collisions
+        .inside
+        .iter()
+        .flat_map(|c| [c.ring_point, c.half_point].map(|p| INSIDE_TRANS.transform_point(p)));
+    let outside = collisions
+        .outside
+        .iter()
+        .flat_map(|c| [c.ring_point, c.half_point].map(|p| OUTSIDE_TRANS.transform_point(p)));
+    inside.chain(outside).collect()
+}
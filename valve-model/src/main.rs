@@ -1,30 +1,110 @@
 #![feature(try_blocks)]
 
+mod analysis;
+mod figure;
+mod geometry;
+mod graph;
+mod keys;
+mod layout;
+mod notation;
+mod procedures;
+#[cfg(test)]
+mod properties;
+mod reassembly;
+mod session;
+mod solver;
+mod tutorial;
 mod valve;
 
-use std::sync::LazyLock;
+use std::{fs::File, io::BufWriter, ops::Range, path::PathBuf};
 
-use crate::valve::{
-    InsidePoint, OutsidePoint, VALVE_MAX_HALF_Y_DISPLACEMENT, VALVE_SECTION_HEIGHT,
-    VALVE_SECTION_WIDTH,
-};
+use crate::valve::{VALVE_MAX_HALF_Y_DISPLACEMENT, VALVE_SECTION_HEIGHT, VALVE_SECTION_WIDTH};
+use analysis::{Analysis, Goal, Hints, Reachability};
+use anyhow::Context;
+use clap::Parser;
 use easycurses::{Color, ColorPair, CursorVisibility, EasyCurses, Input, InputMode};
-use euclid::{Point2D, Translation2D};
+use euclid::Point2D;
+use figure::Format;
+use geometry::Geometry;
+use graph::StateGraph;
 use itertools::{iproduct, Itertools};
+use keys::{Action, KeyBindings, Preset};
+use layout::{
+    collision_positions, piece_at, top_view_cells, valve_cells, BACKGROUND_COLOR, BLOCKED_COLOR,
+    HALF_BACKGROUND_COLOR, HALF_MAIN_COLOR, HINT_COLOR, RING_BACKGROUND_COLOR, RING_MAIN_COLOR,
+};
 use non_empty_collections::NonEmptyIndexSet;
+use notation::{parse_moves, parse_moves_seen_from, parse_moves_with_positions, ParseError};
+use pancurses::MEVENT;
+use procedures::{Guide, REASSEMBLY_FITTING_STEPS};
 use thiserror::Error;
+use tutorial::{ProcedureKind, Progress, Tutorial};
 use undo::Record;
 use valve::{
-    Inside, Move, NonEmptyIndexSetExt, Outside, Ring, RingDirection, Valve, VerticalDirection,
+    Collisions, Move, MoveError, NonEmptyIndexSetExt, Orientation, Piece, RingDirection, Step,
+    Valve, ValveState, VerticalDirection, ViewingSide,
 };
 
 struct Absolute;
 
-const BACKGROUND_COLOR: Color = Color::Blue;
-const RING_MAIN_COLOR: Color = Color::Magenta;
-const RING_BACKGROUND_COLOR: Color = Color::Yellow;
-const HALF_MAIN_COLOR: Color = Color::White;
-const HALF_BACKGROUND_COLOR: Color = Color::Black;
+/// Interactive model of the Hanayama Valve puzzle.
+#[derive(Parser)]
+struct Cli {
+    /// Load the puzzle geometry from a TOML file instead of using the built-in one.
+    #[arg(long, value_name = "FILE")]
+    geometry: Option<PathBuf>,
+    /// Print the shortest disassembly sequence from the starting position and exit.
+    #[arg(long)]
+    solve: bool,
+    /// Print the shortest way to put the puzzle back together from its loose pieces, compared
+    /// with the reassembly in the guide, and exit.
+    #[arg(long)]
+    reassemble: bool,
+    /// Apply the moves in a script to the starting position, print the final state, and exit.
+    #[arg(long, value_name = "FILE")]
+    replay: Option<PathBuf>,
+    /// Also draw the final state of the replayed script to a file in the format of figures.
+    #[arg(long, value_name = "FILE", requires = "replay")]
+    render: Option<PathBuf>,
+    /// Draw every figure of the guide and write the steps of its procedures into a directory and
+    /// exit.
+    #[arg(long, value_name = "DIR")]
+    figures: Option<PathBuf>,
+    /// Outline the pieces moved by the step before each drawn figure, or the last move of a
+    /// replayed script.
+    #[arg(long)]
+    mark_moved: bool,
+    /// The format of drawn figures.
+    #[arg(long, value_enum, default_value_t = Format::Png)]
+    format: Format,
+    /// Label every state reachable from the starting position by whether the puzzle can still be
+    /// taken apart from it, print them, and exit.
+    #[arg(long)]
+    analyze: bool,
+    /// Write the graph of every state reachable from the starting position to a GraphViz DOT file and exit.
+    #[arg(long, value_name = "FILE")]
+    dot: Option<PathBuf>,
+    /// Resume a session saved from the model, including its undo and redo history.
+    #[arg(long, value_name = "FILE")]
+    load: Option<PathBuf>,
+    /// Load key bindings from a TOML file, which may change the keys of any action.
+    #[arg(long, value_name = "FILE")]
+    keys: Option<PathBuf>,
+    /// The side of the puzzle that clockwise and counter-clockwise ring moves are seen from.
+    #[arg(long, value_enum, default_value_t = ViewingSide::Hanayama)]
+    view_from: ViewingSide,
+    /// Write ring moves printed by `--solve` and written by `--dot` as rotations seen from the
+    /// side given by `--view-from`, such as `I-CCW`, rather than as `IL`.
+    #[arg(long)]
+    physical: bool,
+    /// The key bindings to start from, unless the key bindings file names its own.
+    #[arg(long, value_enum, default_value_t = Preset::Arrows)]
+    key_preset: Preset,
+    /// Walk through a procedure of the guide one step at a time, showing how each step is done
+    /// and then waiting for its moves to be made.
+    #[arg(long, value_enum, value_name = "PROCEDURE", conflicts_with = "load")]
+    tutorial: Option<ProcedureKind>,
+}
 
 trait CursesExt {
     fn clear_screen(&mut self) -> Option<()>;
@@ -36,17 +116,45 @@ trait CursesExt {
         msg: &str,
     ) -> Option<()>;
 
-    fn render_points(
+    /// Renders the valve with the cells at the highlighted positions in red.
+    fn render_valve(&mut self, valve: &Valve, highlighted: &[Point2D<i32, Absolute>])
+        -> Option<()>;
+
+    /// Renders the puzzle as seen from above.
+    fn render_top_view(&mut self, valve: &Valve) -> Option<()>;
+
+    /// Renders a move at the current cursor location with rotations seen from a side.
+    fn render_move(&mut self, muv: &Move, side: ViewingSide) -> Option<()>;
+
+    /// Renders the moves at the current cursor location, with the highlighted one in the hint
+    /// color, and returns the columns each one covers.
+    fn render_move_list(
+        &mut self,
+        moves: impl Iterator<Item = Move>,
+        highlighted: Option<&Move>,
+        side: ViewingSide,
+    ) -> Option<Vec<(Range<i32>, Move)>>;
+
+    /// Draws a box listing moves to choose from by number, along with the other choices.
+    fn render_move_menu(
         &mut self,
-        display_char: char,
-        color_pair: ColorPair,
-        points: impl Iterator<Item = Point2D<i32, Absolute>>,
+        position: Point2D<i32, Absolute>,
+        title: &str,
+        moves: &[Move],
+        choices: &[&str],
+        side: ViewingSide,
     ) -> Option<()>;
 
-    fn render_valve(&mut self, valve: &Valve) -> Option<()>;
+    /// Renders the current step of a tutorial, with its text wrapped at the edge of the screen.
+    fn render_tutorial(
+        &mut self,
+        position: Point2D<i32, Absolute>,
+        tutorial: &Tutorial,
+        bindings: &KeyBindings,
+    ) -> Option<()>;
 
-    // At the current cursor location
-    fn render_move_list(&mut self, moves: impl Iterator<Item = Move>) -> Option<()>;
+    /// Reads a line of text typed after a prompt, which is empty if escape is pressed.
+    fn prompt(&mut self, position: Point2D<i32, Absolute>, msg: &str) -> Option<String>;
 }
 impl CursesExt for EasyCurses {
     fn clear_screen(&mut self) -> Option<()> {
@@ -74,147 +182,209 @@ impl CursesExt for EasyCurses {
         self.print(msg)
     }
 
-    fn render_points(
+    fn render_valve(
         &mut self,
-        display_char: char,
-        color_pair: ColorPair,
-        points: impl Iterator<Item = Point2D<i32, Absolute>>,
+        valve: &Valve,
+        highlighted: &[Point2D<i32, Absolute>],
     ) -> Option<()> {
-        for point in points {
-            self.move_rc(point.y, point.x)?;
-            self.set_color_pair(color_pair);
-            self.print_char(display_char)?;
+        for cell in valve_cells(valve) {
+            self.move_rc(cell.position.y, cell.position.x)?;
+            if highlighted.contains(&cell.position) {
+                self.set_color_pair(ColorPair::new(Color::White, BLOCKED_COLOR));
+            } else {
+                self.set_color_pair(cell.color_pair());
+            }
+            self.print_char(cell.glyph)?;
         }
 
         Some(())
     }
 
-    fn render_valve(&mut self, valve: &Valve) -> Option<()> {
-        const START_X: i32 = 1;
-        const INSIDE_TRANS: Translation2D<i32, Inside, Absolute> =
-            Translation2D::new(START_X + 1, valve::VALVE_MAX_HALF_Y_DISPLACEMENT);
-        const OUTSIDE_TRANS: Translation2D<i32, Outside, Absolute> = Translation2D::new(
-            START_X + 1 + VALVE_SECTION_WIDTH + 5,
-            valve::VALVE_MAX_HALF_Y_DISPLACEMENT,
-        );
-        const HANAYAMA_HALF_CHAR: char = 'H';
-        const VALVE_HALF_CHAR: char = 'V';
-        const INNER_RING_CHAR: char = 'I';
-        const OUTER_RING_CHAR: char = 'O';
-
-        // Render inside section
-        self.render_points(
-            HANAYAMA_HALF_CHAR,
-            ColorPair::new(HALF_MAIN_COLOR, HALF_BACKGROUND_COLOR),
-            valve
-                .hanayama_half
-                .inside_points()
-                .iter()
-                .map(|p| INSIDE_TRANS.transform_point(*p)),
-        )?;
-        self.render_points(
-            VALVE_HALF_CHAR,
-            ColorPair::new(HALF_BACKGROUND_COLOR, HALF_MAIN_COLOR),
-            valve
-                .valve_half
-                .inside_points(None)
-                .iter()
-                .map(|p| INSIDE_TRANS.transform_point(*p)),
-        )?;
-        self.render_points(
-            INNER_RING_CHAR,
-            ColorPair::new(RING_MAIN_COLOR, RING_BACKGROUND_COLOR),
-            valve
-                .inner_ring
-                .points(None)
-                .map(|p| INSIDE_TRANS.transform_point(p)),
-        )?;
-        self.render_points(
-            ' ',
-            ColorPair::new(RING_MAIN_COLOR, RING_BACKGROUND_COLOR),
-            [-1, VALVE_SECTION_WIDTH].into_iter().flat_map(|x| {
-                (0..VALVE_SECTION_HEIGHT).map(move |y| {
-                    INSIDE_TRANS
-                        .transform_point(InsidePoint::new(x, y + valve.inner_ring.position().y - 1))
-                })
-            }),
-        )?;
-
-        // Render outside section
-        self.render_points(
-            HANAYAMA_HALF_CHAR,
-            ColorPair::new(HALF_MAIN_COLOR, HALF_BACKGROUND_COLOR),
-            valve
-                .hanayama_half
-                .outside_points()
-                .iter()
-                .map(|p| OUTSIDE_TRANS.transform_point(*p)),
-        )?;
-        self.render_points(
-            VALVE_HALF_CHAR,
-            ColorPair::new(HALF_BACKGROUND_COLOR, HALF_MAIN_COLOR),
-            valve
-                .valve_half
-                .outside_points(None)
-                .iter()
-                .map(|p| OUTSIDE_TRANS.transform_point(*p)),
-        )?;
-        self.render_points(
-            OUTER_RING_CHAR,
-            ColorPair::new(RING_BACKGROUND_COLOR, RING_MAIN_COLOR),
-            valve
-                .outer_ring
-                .points(None)
-                .map(|p| OUTSIDE_TRANS.transform_point(p)),
-        )?;
-        self.render_points(
-            ' ',
-            ColorPair::new(RING_BACKGROUND_COLOR, RING_MAIN_COLOR),
-            [-1, VALVE_SECTION_WIDTH].into_iter().flat_map(|x| {
-                (0..VALVE_SECTION_HEIGHT).map(move |y| {
-                    OUTSIDE_TRANS.transform_point(OutsidePoint::new(
-                        x,
-                        y + valve.outer_ring.position().y - 1,
-                    ))
-                })
-            }),
-        )?;
+    fn render_top_view(&mut self, valve: &Valve) -> Option<()> {
+        for cell in top_view_cells(valve) {
+            self.move_rc(cell.position.y, cell.position.x)?;
+            self.set_color_pair(cell.color_pair());
+            self.print_char(cell.glyph)?;
+        }
 
         Some(())
     }
 
-    fn render_move_list(&mut self, moves: impl Iterator<Item = Move>) -> Option<()> {
-        for muv in moves {
-            match &muv {
-                Move::InnerRingRotate(_) => {
-                    self.set_color_pair(ColorPair::new(RING_MAIN_COLOR, RING_BACKGROUND_COLOR));
-                }
-                Move::OuterRingRotate(_) => {
-                    self.set_color_pair(ColorPair::new(RING_BACKGROUND_COLOR, RING_MAIN_COLOR));
-                }
-                Move::Vertical {
-                    direction: _,
-                    pieces: _,
-                } => {
-                    self.set_color_pair(ColorPair::new(HALF_BACKGROUND_COLOR, HALF_MAIN_COLOR));
-                }
+    fn render_move(&mut self, muv: &Move, side: ViewingSide) -> Option<()> {
+        match muv {
+            Move::InnerRingRotate(_) => {
+                self.set_color_pair(ColorPair::new(RING_MAIN_COLOR, RING_BACKGROUND_COLOR));
+            }
+            Move::OuterRingRotate(_) => {
+                self.set_color_pair(ColorPair::new(RING_BACKGROUND_COLOR, RING_MAIN_COLOR));
+            }
+            Move::Vertical {
+                direction: _,
+                pieces: _,
+            } => {
+                self.set_color_pair(ColorPair::new(HALF_BACKGROUND_COLOR, HALF_MAIN_COLOR));
             }
+        }
+
+        self.print(muv.name(Some(side)))
+    }
 
-            self.print(muv.as_str())?;
+    fn render_move_list(
+        &mut self,
+        moves: impl Iterator<Item = Move>,
+        highlighted: Option<&Move>,
+        side: ViewingSide,
+    ) -> Option<Vec<(Range<i32>, Move)>> {
+        let mut columns = Vec::new();
+        for muv in moves {
+            let start = self.get_cursor_rc().1;
+            if highlighted == Some(&muv) {
+                self.set_color_pair(ColorPair::new(Color::White, HINT_COLOR));
+                self.print(muv.name(Some(side)))?;
+            } else {
+                self.render_move(&muv, side)?;
+            }
+            columns.push((start..self.get_cursor_rc().1, muv));
             self.set_color_pair(ColorPair::new(Color::White, BACKGROUND_COLOR));
             self.print_char(' ')?;
         }
 
+        Some(columns)
+    }
+
+    fn render_move_menu(
+        &mut self,
+        position: Point2D<i32, Absolute>,
+        title: &str,
+        moves: &[Move],
+        choices: &[&str],
+        side: ViewingSide,
+    ) -> Option<()> {
+        let numbered = moves
+            .iter()
+            .enumerate()
+            .map(|(i, muv)| (format!("{}: ", i + 1), Some(muv)));
+        let lines = std::iter::once((title.to_string(), None))
+            .chain(numbered)
+            .chain(choices.iter().map(|c| (c.to_string(), None)))
+            .collect_vec();
+
+        let width = lines
+            .iter()
+            .map(|(text, muv)| text.len() + muv.map_or(0, |m| m.name(Some(side)).len()))
+            .max()
+            .unwrap_or(0);
+        let border = format!("+{}+", "-".repeat(width + 2));
+        let color_pair = Some(ColorPair::new(Color::White, Color::Black));
+
+        self.text(position, color_pair, &border)?;
+        for (dy, (text, muv)) in (1..).zip(lines.iter()) {
+            let row = position + euclid::vec2(0, dy);
+            self.text(row, color_pair, &format!("| {text:width$} |"))?;
+            if let Some(muv) = muv {
+                self.move_rc(row.y, row.x + 2 + i32::try_from(text.len()).unwrap())?;
+                self.render_move(muv, side)?;
+            }
+        }
+        self.text(
+            position + euclid::vec2(0, i32::try_from(lines.len()).unwrap() + 1),
+            color_pair,
+            &border,
+        )
+    }
+
+    fn render_tutorial(
+        &mut self,
+        position: Point2D<i32, Absolute>,
+        tutorial: &Tutorial,
+        bindings: &KeyBindings,
+    ) -> Option<()> {
+        let width = usize::try_from(self.get_row_col_count().1 - position.x).unwrap_or(0);
+        let last = tutorial.steps.last().map_or(0, |step| step.number);
+
+        let mut lines = Vec::new();
+        match tutorial.step() {
+            Some(step) => {
+                lines.push(format!(
+                    "{}, step {} of {last}",
+                    tutorial.kind.name(),
+                    step.number
+                ));
+                lines.push(String::new());
+                lines.extend(wrap(&step.text, width));
+                lines.push(String::new());
+                lines.push(format!(
+                    "Moves made: {} of {}",
+                    tutorial.moves_made_in_step(),
+                    step.moves.len()
+                ));
+                lines.push(format!(
+                    "Watch this step again: {}",
+                    bindings.key_names(Action::Hint)
+                ));
+            }
+            None => {
+                lines.push(format!("{} finished!", tutorial.kind.name()));
+                lines.push(format!("Start over: {}", bindings.key_names(Action::Reset)));
+            }
+        }
+
+        for (dy, line) in (0..).zip(lines.iter()) {
+            self.text(position + euclid::vec2(0, dy), None, line)?;
+        }
         Some(())
     }
+
+    fn prompt(&mut self, position: Point2D<i32, Absolute>, msg: &str) -> Option<String> {
+        let mut line = String::new();
+
+        loop {
+            // Clear the rest of the row before redrawing the line with a cursor
+            let width = self.get_row_col_count().1;
+            self.text(position, None, &" ".repeat(usize::try_from(width).unwrap()))?;
+            self.text(position, None, &format!("{msg}{line}_"))?;
+            self.refresh()?;
+
+            match self.get_input()? {
+                Input::Character('\n') => return Some(line),
+                Input::Character('\u{1b}') => return Some(String::new()),
+                Input::KeyBackspace | Input::Character('\u{7f}') | Input::Character('\u{8}') => {
+                    line.pop();
+                }
+                Input::Character(c) => line.push(c),
+                _ => {}
+            }
+        }
+    }
+}
+
+/// Splits text between words into lines no longer than `width` where possible.
+fn wrap(text: &str, width: usize) -> Vec<String> {
+    let mut lines: Vec<String> = Vec::new();
+    for word in text.split_whitespace() {
+        match lines.last_mut() {
+            Some(line) if line.len() + 1 + word.len() <= width => {
+                line.push(' ');
+                line.push_str(word);
+            }
+            _ => lines.push(word.to_string()),
+        }
+    }
+    lines
 }
 
 #[derive(Error, Debug)]
 enum ValveError {
     #[error("curses function failed")]
     Curses,
-    #[error("the command was ambiguous")]
-    AmbiguousCommand,
+    #[error("{0}")]
+    Notation(#[from] ParseError),
+    #[error("could not make move {index}")]
+    IllegalMove {
+        index: usize,
+        #[source]
+        source: MoveError,
+    },
 }
 
 /// Unforutnately [`easycurses`] functions usually return [`Option<()>`],
@@ -229,10 +399,47 @@ impl<T> OptionExt<T> for Option<T> {
     }
 }
 
+/// Which of several possible vertical moves to make without asking.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum VerticalPreference {
+    Ask,
+    FewestPieces,
+    MostPieces,
+}
+impl VerticalPreference {
+    fn name(&self) -> &'static str {
+        match self {
+            VerticalPreference::Ask => "Ask",
+            VerticalPreference::FewestPieces => "Fewest pieces",
+            VerticalPreference::MostPieces => "Most pieces",
+        }
+    }
+
+    /// Keeps only the preferred moves, which may still leave more than one to choose from.
+    fn filter(&self, moves: Vec<Move>) -> Vec<Move> {
+        let count = |muv: &Move| muv.pieces().len();
+        let best = match self {
+            VerticalPreference::Ask => return moves,
+            VerticalPreference::FewestPieces => moves.iter().map(count).min(),
+            VerticalPreference::MostPieces => moves.iter().map(count).max(),
+        };
+        moves
+            .into_iter()
+            .filter(|muv| Some(count(muv)) == best)
+            .collect()
+    }
+}
+
 struct State {
     valve: Valve,
-    record: Record<Move>,
+    record: Record<Step>,
     move_inner: bool,
+    gravity: Option<Orientation>,
+    vertical_preference: VerticalPreference,
+    /// What was in the way of the last rotation, if it was blocked.
+    blocked: Option<Collisions>,
+    /// What hints lead toward, which is back to the start once the puzzle has come apart.
+    goal: Goal,
 }
 impl Default for State {
     fn default() -> Self {
@@ -240,13 +447,224 @@ impl Default for State {
             valve: Valve::default(),
             record: Record::new(),
             move_inner: true,
+            gravity: None,
+            vertical_preference: VerticalPreference::Ask,
+            blocked: None,
+            goal: Goal::default(),
         }
     }
 }
+impl State {
+    /// Starts over from a state, keeping the settings chosen so far.
+    fn reset(&mut self, valve: Valve) {
+        self.valve = valve;
+        self.record = Record::new();
+        self.blocked = None;
+        self.goal = Goal::default();
+    }
+}
 
 fn main() -> anyhow::Result<()> {
+    let cli = Cli::parse();
+
+    if let Some(path) = &cli.geometry {
+        Geometry::load(path)
+            .with_context(|| format!("invalid geometry file `{}`", path.display()))?
+            .install();
+    }
+
+    // How moves are written by the commands that print or export them
+    let names = cli.physical.then_some(cli.view_from);
+
+    if cli.solve {
+        match solver::solve(&Valve::default()) {
+            Some(moves) => {
+                println!("Solved in {} moves:", moves.len());
+                println!("{}", moves.iter().map(|m| m.name(names)).join(" "));
+            }
+            None => println!("No solution found!"),
+        }
+        return Ok(());
+    }
+
+    if cli.reassemble {
+        let start = ValveState::default();
+        match reassembly::solve() {
+            Some(r) => {
+                println!("To put the loose pieces back together, {}.", r.insertion);
+                println!(
+                    "Then the starting position is {} moves away from {}:",
+                    r.moves.len(),
+                    r.insertion.state
+                );
+                println!("{}", r.moves.iter().map(|m| m.name(names)).join(" "));
+            }
+            None => println!("No way back to the starting position found!"),
+        }
+
+        // The guide's own way of fitting the pieces together may not be the best one
+        let guide = Guide::get().reassembly;
+        let guide_moves: usize = guide
+            .steps
+            .iter()
+            .map(|s| {
+                parse_moves(&s.moves)
+                    .expect("built-in guide moves are valid")
+                    .len()
+            })
+            .sum();
+        println!(
+            "The guide takes {} steps, fitting the pieces together in {REASSEMBLY_FITTING_STEPS}, \
+             the last of which turns the outer ring halfway around, and then making {guide_moves} \
+             moves in the rest.",
+            REASSEMBLY_FITTING_STEPS + guide.steps.len()
+        );
+        let fitted = ValveState::from(guide.start);
+        if let Some((_, moves)) = solver::search([fitted], |v| v.state() == start) {
+            println!(
+                "From where the guide fits the pieces together, the fewest moves are {}:",
+                moves.len()
+            );
+            println!("{}", moves.iter().map(|m| m.name(names)).join(" "));
+        }
+        return Ok(());
+    }
+
+    if let Some(path) = cli.replay {
+        let script = std::fs::read_to_string(&path)
+            .with_context(|| format!("could not read `{}`", path.display()))?;
+        let moves = parse_moves_with_positions(&script, cli.view_from)
+            .with_context(|| format!("invalid move script `{}`", path.display()))?;
+
+        let mut valve = Valve::default();
+        for (i, (muv, line, column)) in moves.iter().enumerate() {
+            let from = valve.state();
+            valve.make_move(muv).with_context(|| {
+                format!(
+                    "could not make move {} at line {line}, column {column} from {from}",
+                    i + 1
+                )
+            })?;
+        }
+
+        println!("Final state: {}", valve.state());
+        if valve.is_separated() {
+            println!("The puzzle is apart!");
+        }
+
+        if let Some(path) = cli.render {
+            let moved = match moves.last() {
+                Some((muv, _, _)) if cli.mark_moved => muv.pieces(),
+                _ => Vec::new(),
+            };
+            figure::write_figure(&path, &valve_cells(&valve), &moved, cli.format)
+                .with_context(|| format!("could not draw `{}`", path.display()))?;
+        }
+        return Ok(());
+    }
+
+    if let Some(dir) = cli.figures {
+        std::fs::create_dir_all(&dir)
+            .with_context(|| format!("could not create `{}`", dir.display()))?;
+
+        // The steps of the procedures, which the guide lists along with their figures
+        let guide = Guide::get();
+        for (name, procedure) in [
+            ("disassembly", &guide.disassembly),
+            ("reassembly", &guide.reassembly),
+        ] {
+            let path = dir.join(format!("{name}-steps.tex"));
+            procedure
+                .write_tex(&mut BufWriter::new(File::create(&path)?))
+                .with_context(|| format!("could not write `{}`", path.display()))?;
+        }
+
+        let figures = guide.all_figures();
+        for figure in figures.iter() {
+            let cells = valve_cells(&Valve::from(ValveState::from(figure.state)))
+                .into_iter()
+                .filter(|c| figure.pieces.as_ref().is_none_or(|p| p.contains(&c.piece)))
+                .collect_vec();
+
+            let path = dir.join(format!("{}.{}", figure.figure, cli.format.extension()));
+            let moved = if cli.mark_moved {
+                figure.moved.as_slice()
+            } else {
+                &[]
+            };
+            figure::write_figure(&path, &cells, moved, cli.format)
+                .with_context(|| format!("could not draw `{}`", path.display()))?;
+        }
+        println!("Wrote {} figures", figures.len());
+        return Ok(());
+    }
+
+    if cli.analyze {
+        let analysis = Analysis::new(&StateGraph::explore(&Valve::default()));
+        for (state, a) in analysis.iter() {
+            let to_goal = a.to_goal.map_or("-".to_string(), |d| d.to_string());
+            println!(
+                "{state}: {} ({} from the start, {to_goal} to go)",
+                a.reachability.name(),
+                a.from_start
+            );
+        }
+
+        let counts = analysis.iter().counts_by(|(_, a)| a.reachability);
+        for reachability in [
+            Reachability::ShortestPath,
+            Reachability::CanSeparate,
+            Reachability::OnlyStart,
+        ] {
+            println!(
+                "{}: {} states",
+                reachability.name(),
+                counts.get(&reachability).unwrap_or(&0)
+            );
+        }
+        return Ok(());
+    }
+
+    if let Some(path) = cli.dot {
+        let graph = StateGraph::explore(&Valve::default());
+        graph.write_dot(&mut BufWriter::new(File::create(path)?), names)?;
+        println!("Wrote {} states", graph.states.len());
+        return Ok(());
+    }
+
+    // Every state reachable from the start, to label the current one with
+    let analysis = Analysis::new(&StateGraph::explore(&Valve::default()));
+
+    let hints = Hints::default();
+
     // The state
     let mut state = State::default();
+    if let Some(path) = &cli.load {
+        (state.valve, state.record) = session::load(path)
+            .with_context(|| format!("invalid session file `{}`", path.display()))?;
+    }
+
+    // The tutorial starts where its procedure does, after showing how the first step is done
+    let mut tutorial = cli
+        .tutorial
+        .map(Tutorial::new)
+        .transpose()
+        .context("could not follow the guide with this geometry")?;
+    if let Some(t) = &tutorial {
+        state.valve = Valve::from(t.start());
+    }
+    let mut show_step = tutorial.is_some();
+
+    let bindings = match &cli.keys {
+        Some(path) => KeyBindings::load(path, cli.key_preset)
+            .with_context(|| format!("invalid key bindings file `{}`", path.display()))?,
+        None => KeyBindings::preset(cli.key_preset),
+    };
+    let mut help_messages = bindings.help();
+    help_messages.push(
+        "Mouse: click a ring to select it, scroll to move it, click an available move to make it"
+            .to_string(),
+    );
 
     // Setup curses
     let mut curses = EasyCurses::initialize_system().ok_valve()?;
@@ -256,16 +674,75 @@ fn main() -> anyhow::Result<()> {
     curses.set_input_mode(InputMode::Character).ok_valve()?;
     curses.set_echo(false).ok_valve()?;
     curses.set_keypad_enabled(true).ok_valve()?;
+    // Report clicks straight away rather than waiting to see if they are double clicks
+    pancurses::mousemask(
+        pancurses::BUTTON1_PRESSED | pancurses::BUTTON4_PRESSED | pancurses::BUTTON5_PRESSED,
+        std::ptr::null_mut(),
+    );
+    pancurses::mouseinterval(0);
 
     let mut error_message: Option<String> = None;
+    let mut info_message: Option<String> = None;
+    // The suggested next move, which is only highlighted until the next key
+    let mut hint: Option<Move> = None;
 
     const HUD_START_Y: i32 = VALVE_SECTION_HEIGHT + 2 * VALVE_MAX_HALF_Y_DISPLACEMENT;
-    const HUD_HELP_START_Y: i32 = HUD_START_Y + 3;
+    const HUD_HELP_START_Y: i32 = HUD_START_Y + 4;
+    // To the right of the valve
+    const MENU_X: i32 = 24;
+    // To the right of the top view
+    const TUTORIAL_X: i32 = 48;
 
     loop {
+        if let Some(t) = &mut tutorial {
+            match t.follow(state.valve.state()) {
+                Progress::Forward {
+                    finished_step: true,
+                } => show_step = true,
+                Progress::Wrong => {
+                    // Take the move back and point out the right one instead
+                    state.record.undo(&mut state.valve);
+                    curses.beep();
+                    hint = t.next_move().cloned();
+                    error_message = hint.as_ref().map(|muv| {
+                        format!(
+                            "That is not the next move, which is {}",
+                            muv.name(Some(cli.view_from))
+                        )
+                    });
+                }
+                Progress::Same | Progress::Forward { .. } | Progress::Back => {}
+            }
+
+            if std::mem::take(&mut show_step) {
+                play_step(
+                    &mut curses,
+                    t,
+                    &bindings,
+                    HUD_START_Y,
+                    TUTORIAL_X,
+                    cli.view_from,
+                )?;
+            }
+        }
+
+        state.goal = state.goal.update(&state.valve);
+
         // Clear and render valve
         curses.clear_screen().ok_valve()?;
-        curses.render_valve(&state.valve);
+        // Anything that blocked the last move is only shown until the next one
+        let blocked = state
+            .blocked
+            .take()
+            .map(|c| collision_positions(&c))
+            .unwrap_or_default();
+        curses.render_valve(&state.valve, &blocked);
+        curses.render_top_view(&state.valve);
+        if let Some(t) = &tutorial {
+            curses
+                .render_tutorial(Point2D::new(TUTORIAL_X, 0), t, &bindings)
+                .ok_valve()?;
+        }
 
         // Render selected ring
         curses
@@ -279,28 +756,81 @@ fn main() -> anyhow::Result<()> {
             curses.print("Outer").ok_valve()?;
         }
 
+        // Render gravity setting
+        curses
+            .text(
+                Point2D::new(22, HUD_START_Y),
+                None,
+                &format!(
+                    "Gravity: {}",
+                    state.gravity.map(|o| o.name()).unwrap_or("Off")
+                ),
+            )
+            .ok_valve()?;
+
+        // Render vertical move preference
+        curses
+            .text(
+                Point2D::new(46, HUD_START_Y),
+                None,
+                &format!("Vertical moves: {}", state.vertical_preference.name()),
+            )
+            .ok_valve()?;
+
+        // Render the side that rotations are seen from
+        curses
+            .text(
+                Point2D::new(46, HUD_START_Y + 2),
+                None,
+                &format!("Seen from: {}", cli.view_from.name()),
+            )
+            .ok_valve()?;
+
+        // Render whether the puzzle can still come apart from here and how soon
+        let (reachability, color_pair) = match analysis.get(&state.valve.state()) {
+            Some(a) => (
+                match a.to_goal {
+                    Some(1) => format!("{}, 1 move to go", a.reachability.name()),
+                    Some(d) => format!("{}, {d} moves to go", a.reachability.name()),
+                    None => a.reachability.name().to_string(),
+                },
+                match a.reachability {
+                    Reachability::ShortestPath => Some(ColorPair::new(Color::White, Color::Green)),
+                    Reachability::CanSeparate => None,
+                    Reachability::OnlyStart => Some(ColorPair::new(Color::White, Color::Red)),
+                },
+            ),
+            None => ("Only reachable after coming apart".to_string(), None),
+        };
+        curses
+            .text(Point2D::new(0, HUD_START_Y + 3), color_pair, &reachability)
+            .ok_valve()?;
+
         // Render available move list
         curses
             .text(Point2D::new(0, HUD_START_Y + 1), None, "Available moves: ")
             .ok_valve()?;
-        curses
-            .render_move_list(Move::iter().filter(|m| state.valve.can_move(m)))
+        let available_moves = curses
+            .render_move_list(
+                Move::iter().filter(|m| state.valve.can_move(m)),
+                hint.take().as_ref(),
+                cli.view_from,
+            )
             .ok_valve()?;
 
-        static HELP_MESSAGES: LazyLock<[&str; 7]> = LazyLock::new(|| {
-            [
-                "Move current ring: <left or right arrows>",
-                "Move valve half: <up or down arrows>",
-                "Change current ring: <space bar>",
-                "Undo: -",
-                "Redo: +",
-                "Reset: R",
-                "Quit: Q",
-            ]
-        });
+        // Announce if the puzzle has come apart
+        if state.valve.is_separated() {
+            curses
+                .text(
+                    Point2D::new(0, HUD_START_Y + 2),
+                    Some(ColorPair::new(Color::White, Color::Green)),
+                    "The puzzle is apart!",
+                )
+                .ok_valve()?;
+        }
 
         // Render help messages
-        for (dy, msg) in HELP_MESSAGES.iter().copied().enumerate() {
+        for (dy, msg) in help_messages.iter().enumerate() {
             curses
                 .text(
                     Point2D::new(0, HUD_HELP_START_Y + i32::try_from(dy).unwrap()),
@@ -310,35 +840,135 @@ fn main() -> anyhow::Result<()> {
                 .ok_valve()?;
         }
 
-        // Render error message (if any)
+        // Render error or other message (if any)
+        let message_y = HUD_HELP_START_Y + i32::try_from(help_messages.len()).unwrap();
         if let Some(m) = error_message.take() {
             curses
                 .text(
-                    Point2D::new(
-                        0,
-                        HUD_HELP_START_Y + i32::try_from(HELP_MESSAGES.len()).unwrap(),
-                    ),
+                    Point2D::new(0, message_y),
                     Some(ColorPair::new(Color::White, Color::Red)),
                     &m,
                 )
                 .ok_valve()?;
+        } else if let Some(m) = info_message.take() {
+            curses
+                .text(Point2D::new(0, message_y), None, &m)
+                .ok_valve()?;
         }
 
         // Update screen
         curses.refresh();
 
         loop {
-            match process_input(&mut state, curses.get_input().ok_valve()?) {
+            let processed = match curses.get_input().ok_valve()? {
+                Input::KeyMouse => match pancurses::getmouse() {
+                    Ok(event) => {
+                        process_mouse(&mut state, event, HUD_START_Y + 1, &available_moves)
+                    }
+                    Err(_) => continue,
+                },
+                input => match (bindings.action(input), &tutorial) {
+                    // Start the tutorial over rather than going to the starting position
+                    (Some(Action::Reset), Some(t)) => {
+                        state.reset(Valve::from(t.start()));
+                        show_step = true;
+                        Processed::Refresh(false)
+                    }
+                    (Some(action), _) => process_input(&mut state, action),
+                    (None, _) => continue,
+                },
+            };
+            match processed {
                 Processed::Quit => return Ok(()),
-                Processed::Nothing => {}
                 Processed::Refresh(beep) => {
                     if beep {
                         curses.beep();
                     }
                     break;
                 }
-                Processed::Error(e) => {
-                    error_message = Some(format!("ERROR: {e}"));
+                Processed::EnterMoves => {
+                    let text = curses
+                        .prompt(Point2D::new(0, message_y), "Moves: ")
+                        .ok_valve()?;
+                    if let Err(e) = apply_moves(&mut state, &text, cli.view_from) {
+                        error_message = Some(format!("ERROR: {:#}", anyhow::Error::from(e)));
+                    }
+                    break;
+                }
+                Processed::ChooseMove(mut moves) => {
+                    // Keep asking until one move is left, as a preference may only narrow them down
+                    while moves.len() > 1 {
+                        curses
+                            .render_move_menu(
+                                Point2D::new(MENU_X, 0),
+                                "Make which move?",
+                                &moves,
+                                &[
+                                    "F: always prefer the fewest pieces",
+                                    "M: always prefer the most pieces",
+                                    "Esc: cancel",
+                                ],
+                                cli.view_from,
+                            )
+                            .ok_valve()?;
+                        curses.refresh();
+
+                        match curses.get_input().ok_valve()? {
+                            Input::Character(c @ '1'..='9') => {
+                                let i = c.to_digit(10).unwrap() as usize - 1;
+                                if i < moves.len() {
+                                    moves = vec![moves.swap_remove(i)];
+                                }
+                            }
+                            Input::Character('f') => {
+                                state.vertical_preference = VerticalPreference::FewestPieces;
+                                moves = state.vertical_preference.filter(moves);
+                            }
+                            Input::Character('m') => {
+                                state.vertical_preference = VerticalPreference::MostPieces;
+                                moves = state.vertical_preference.filter(moves);
+                            }
+                            Input::Character('\u{1b}') => moves.clear(),
+                            _ => {}
+                        }
+                    }
+
+                    if let Some(muv) = moves.pop() {
+                        state.record.edit(&mut state.valve, muv.into());
+                    }
+                    break;
+                }
+                // The tutorial shows the step again, and its next move rather than the fewest
+                Processed::Hint if tutorial.is_some() => {
+                    show_step = true;
+                    hint = tutorial.as_ref().and_then(|t| t.next_move().cloned());
+                    break;
+                }
+                Processed::Hint => {
+                    hint = hints.best_move(&state.valve, state.goal);
+                    match &hint {
+                        Some(muv) => {
+                            info_message = Some(format!("Hint: {}", muv.name(Some(cli.view_from))))
+                        }
+                        None => {
+                            curses.beep();
+                            info_message = Some("There is no hint from here".to_string());
+                        }
+                    }
+                    break;
+                }
+                Processed::SaveSession => {
+                    let path = curses
+                        .prompt(Point2D::new(0, message_y), "Save session to: ")
+                        .ok_valve()?;
+                    if !path.is_empty() {
+                        match session::save(path.as_ref(), &state.record) {
+                            Ok(()) => info_message = Some(format!("Saved session to {path}")),
+                            Err(e) => {
+                                error_message = Some(format!("ERROR: {:#}", anyhow::Error::from(e)))
+                            }
+                        }
+                    }
                     break;
                 }
             }
@@ -346,14 +976,137 @@ fn main() -> anyhow::Result<()> {
     }
 }
 
+/// Shows how the current step of a tutorial is done by making its moves one at a time from where
+/// the step starts, naming each move in the HUD.
+fn play_step(
+    curses: &mut EasyCurses,
+    tutorial: &Tutorial,
+    bindings: &KeyBindings,
+    hud_y: i32,
+    tutorial_x: i32,
+    side: ViewingSide,
+) -> Result<(), ValveError> {
+    const MOVE_DELAY_MS: i32 = 800;
+
+    let Some(step) = tutorial.step() else {
+        return Ok(());
+    };
+    for (i, state) in tutorial.step_states().iter().enumerate() {
+        curses.clear_screen().ok_valve()?;
+        curses.render_valve(&Valve::from(*state), &[]).ok_valve()?;
+        curses.render_top_view(&Valve::from(*state)).ok_valve()?;
+        curses
+            .render_tutorial(Point2D::new(tutorial_x, 0), tutorial, bindings)
+            .ok_valve()?;
+
+        let msg = match i.checked_sub(1) {
+            None => format!("Watch step {}:", step.number),
+            Some(m) => format!(
+                "Watch step {}: move {} of {}, {}",
+                step.number,
+                m + 1,
+                step.moves.len(),
+                step.moves[m].name(Some(side))
+            ),
+        };
+        curses.text(Point2D::new(0, hud_y), None, &msg).ok_valve()?;
+        curses.refresh();
+        pancurses::napms(MOVE_DELAY_MS);
+    }
+
+    // Keys pressed while watching would otherwise be taken as moves afterward
+    pancurses::flushinp();
+    Ok(())
+}
+
+/// Makes a sequence of moves in notation, with rotations seen from a side, as a single step,
+/// provided that every move is possible.
+fn apply_moves(state: &mut State, text: &str, side: ViewingSide) -> Result<(), ValveError> {
+    let moves = parse_moves_seen_from(text, side)?;
+    if moves.is_empty() {
+        return Ok(());
+    }
+
+    let mut valve = state.valve.clone();
+    for (i, muv) in moves.iter().enumerate() {
+        valve
+            .make_move(muv)
+            .map_err(|source| ValveError::IllegalMove {
+                index: i + 1,
+                source,
+            })?;
+    }
+
+    state.record.edit(&mut state.valve, Step(moves));
+    Ok(())
+}
+
 enum Processed {
     Quit,
-    Nothing,
     Refresh(bool),
-    Error(ValveError),
+    EnterMoves,
+    SaveSession,
+    /// Suggest the next move.
+    Hint,
+    /// Ask which of several possible moves to make.
+    ChooseMove(Vec<Move>),
+}
+
+/// Selects a clicked ring, rotates the selected ring when scrolling, or makes a clicked move
+/// from the list of available moves on the given row.
+fn process_mouse(
+    state: &mut State,
+    event: MEVENT,
+    moves_y: i32,
+    available_moves: &[(Range<i32>, Move)],
+) -> Processed {
+    if event.bstate & pancurses::BUTTON4_PRESSED != 0 {
+        return process_input(state, Action::RotateLeft);
+    }
+    if event.bstate & pancurses::BUTTON5_PRESSED != 0 {
+        return process_input(state, Action::RotateRight);
+    }
+    if event.bstate & pancurses::BUTTON1_PRESSED == 0 {
+        return Processed::Refresh(false);
+    }
+
+    if event.y == moves_y {
+        return match available_moves.iter().find(|(x, _)| x.contains(&event.x)) {
+            // Rotations are made as with the keys, so that pieces fall after them with gravity on
+            Some((_, muv @ (Move::InnerRingRotate(_) | Move::OuterRingRotate(_)))) => {
+                rotate(state, muv, 1)
+            }
+            Some((_, muv)) => {
+                state.record.edit(&mut state.valve, muv.clone().into());
+                Processed::Refresh(false)
+            }
+            None => Processed::Refresh(false),
+        };
+    }
+
+    match piece_at(&state.valve, Point2D::new(event.x, event.y)) {
+        Some(Piece::InnerRing) => state.move_inner = true,
+        Some(Piece::OuterRing) => state.move_inner = false,
+        _ => {}
+    }
+    Processed::Refresh(false)
+}
+
+/// Makes a ring rotation up to `segments` times as a single step, as in [`Valve::rotations`].
+fn rotate(state: &mut State, muv: &Move, segments: i32) -> Processed {
+    if !state.valve.can_move(muv) {
+        state.blocked = Some(state.valve.collisions(muv));
+        return Processed::Refresh(true);
+    }
+
+    let moves = state.valve.rotations(muv, segments, state.gravity);
+    if !moves.is_empty() {
+        state.record.edit(&mut state.valve, Step(moves));
+    }
+    Processed::Refresh(false)
 }
 
-fn process_input(state: &mut State, input: Input) -> Processed {
+fn process_input(state: &mut State, action: Action) -> Processed {
     fn move_vertical(state: &mut State, dir: VerticalDirection) -> Processed {
         let moves = NonEmptyIndexSet::vertical_piece_iter()
             .map(|pieces| Move::Vertical {
@@ -363,52 +1116,65 @@ fn process_input(state: &mut State, input: Input) -> Processed {
             .filter(|muv| state.valve.can_move(muv))
             .collect_vec();
 
+        let moves = state.vertical_preference.filter(moves);
         match moves.len() {
             0 => Processed::Refresh(true),
             1 => {
                 state
                     .record
-                    .edit(&mut state.valve, moves.into_iter().next().unwrap());
+                    .edit(&mut state.valve, moves.into_iter().next().unwrap().into());
                 Processed::Refresh(false)
             }
-            _ => Processed::Error(ValveError::AmbiguousCommand),
+            _ => Processed::ChooseMove(moves),
         }
     }
 
-    fn move_ring(state: &mut State, dir: RingDirection) -> Processed {
+    fn move_ring(state: &mut State, dir: RingDirection, segments: i32) -> Processed {
         let muv = if state.move_inner {
             Move::InnerRingRotate(dir)
         } else {
             Move::OuterRingRotate(dir)
         };
-
-        Processed::Refresh(if state.valve.can_move(&muv) {
-            state.record.edit(&mut state.valve, muv);
-            false
-        } else {
-            true
-        })
+        rotate(state, &muv, segments)
     }
 
-    match input {
-        Input::Character(c) => match c {
-            ' ' => {
-                state.move_inner = !state.move_inner;
-                Processed::Refresh(false)
-            }
-            '-' => Processed::Refresh(state.record.undo(&mut state.valve).is_none()),
-            '+' => Processed::Refresh(state.record.redo(&mut state.valve).is_none()),
-            'q' => Processed::Quit,
-            'r' => {
-                *state = State::default();
-                Processed::Refresh(false)
-            }
-            _ => Processed::Nothing,
-        },
-        Input::KeyLeft => move_ring(state, RingDirection::Left),
-        Input::KeyRight => move_ring(state, RingDirection::Right),
-        Input::KeyUp => move_vertical(state, VerticalDirection::Up),
-        Input::KeyDown => move_vertical(state, VerticalDirection::Down),
-        _ => Processed::Nothing,
+    match action {
+        Action::ChangeRing => {
+            state.move_inner = !state.move_inner;
+            Processed::Refresh(false)
+        }
+        Action::Undo => Processed::Refresh(state.record.undo(&mut state.valve).is_none()),
+        Action::Redo => Processed::Refresh(state.record.redo(&mut state.valve).is_none()),
+        Action::ToggleGravity => {
+            state.gravity = match state.gravity {
+                None => Some(Orientation::HanayamaUp),
+                Some(Orientation::HanayamaUp) => Some(Orientation::ValveUp),
+                Some(Orientation::ValveUp) => None,
+            };
+            Processed::Refresh(false)
+        }
+        Action::ChangeVerticalPreference => {
+            state.vertical_preference = match state.vertical_preference {
+                VerticalPreference::Ask => VerticalPreference::FewestPieces,
+                VerticalPreference::FewestPieces => VerticalPreference::MostPieces,
+                VerticalPreference::MostPieces => VerticalPreference::Ask,
+            };
+            Processed::Refresh(false)
+        }
+        Action::EnterMoves => Processed::EnterMoves,
+        Action::SaveSession => Processed::SaveSession,
+        Action::Hint => Processed::Hint,
+        Action::Quit => Processed::Quit,
+        Action::Reset => {
+            state.reset(Valve::default());
+            Processed::Refresh(false)
+        }
+        Action::RotateLeft => move_ring(state, RingDirection::Left, 1),
+        Action::RotateRight => move_ring(state, RingDirection::Right, 1),
+        // Rotate until the ring stops, but at most a full turn
+        Action::RotateLeftUntilStop => move_ring(state, RingDirection::Left, VALVE_SECTION_WIDTH),
+        Action::RotateRightUntilStop => move_ring(state, RingDirection::Right, VALVE_SECTION_WIDTH),
+        Action::MoveUp => move_vertical(state, VerticalDirection::Up),
+        Action::MoveDown => move_vertical(state, VerticalDirection::Down),
     }
 }
@@ -0,0 +1,348 @@
+use non_empty_collections::NonEmptyIndexSet;
+use std::{iter::Peekable, str::Chars, str::FromStr};
+use strum::IntoEnumIterator;
+use thiserror::Error;
+
+use crate::valve::{
+    InnerRing, Move, OuterRing, Ring, RingDirection, Rotation, VerticalDirection, VerticalPieces,
+    ViewingSide, VALVE_SECTION_HEIGHT,
+};
+
+/// The largest repeat count, which is more than any piece can move in a row since none travels
+/// further than twice the section height.
+const MAX_COUNT: usize = 2 * VALVE_SECTION_HEIGHT as usize;
+
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+pub enum ParseErrorKind {
+    #[error("expected a move, found `{0}`")]
+    ExpectedMove(char),
+    #[error("expected `L`, `R`, `-CW` or `-CCW` after the ring")]
+    ExpectedRingDirection,
+    #[error("expected `{{` after the vertical direction")]
+    ExpectedOpenBrace,
+    #[error("expected a piece (`I`, `O` or `V`)")]
+    ExpectedPiece,
+    #[error("expected `,` or `}}` after a piece")]
+    ExpectedSeparator,
+    #[error("the piece `{0}` is listed more than once")]
+    DuplicatePiece(char),
+    #[error("the repeat count must be from 1 to {MAX_COUNT}")]
+    InvalidCount,
+    #[error("unexpected input after the move")]
+    Trailing,
+    #[error("unexpected end of input")]
+    UnexpectedEnd,
+}
+
+/// An error parsing move notation, with the 1-based line and column at which it occurred.
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+#[error("line {line}, column {column}: {kind}")]
+pub struct ParseError {
+    pub line: usize,
+    pub column: usize,
+    pub kind: ParseErrorKind,
+}
+
+struct Parser<'a> {
+    chars: Peekable<Chars<'a>>,
+    line: usize,
+    column: usize,
+    /// The side that clockwise and counter-clockwise rotations are seen from.
+    side: ViewingSide,
+}
+impl<'a> Parser<'a> {
+    fn new(s: &'a str, side: ViewingSide) -> Self {
+        Self {
+            chars: s.chars().peekable(),
+            line: 1,
+            column: 1,
+            side,
+        }
+    }
+
+    fn error(&self, kind: ParseErrorKind) -> ParseError {
+        ParseError {
+            line: self.line,
+            column: self.column,
+            kind,
+        }
+    }
+
+    fn peek(&mut self) -> Option<char> {
+        self.chars.peek().copied()
+    }
+
+    fn next(&mut self) -> Option<char> {
+        let c = self.chars.next()?;
+        if c == '\n' {
+            self.line += 1;
+            self.column = 1;
+        } else {
+            self.column += 1;
+        }
+        Some(c)
+    }
+
+    /// Consumes the next character, which must be present.
+    fn next_required(&mut self) -> Result<char, ParseError> {
+        self.next()
+            .ok_or_else(|| self.error(ParseErrorKind::UnexpectedEnd))
+    }
+
+    /// Skips whitespace along with `#` comments, which run until the end of the line.
+    fn skip_blanks(&mut self) {
+        while let Some(c) = self.peek() {
+            if c == '#' {
+                while self.peek().is_some_and(|c| c != '\n') {
+                    self.next();
+                }
+            } else if c.is_whitespace() {
+                self.next();
+            } else {
+                break;
+            }
+        }
+    }
+
+    /// Either a direction along the sections of the model, or a rotation such as `-CCW` that
+    /// is turned into one for the ring `R`.
+    fn ring_direction<R: Ring>(&mut self) -> Result<RingDirection, ParseError> {
+        let error = self.error(ParseErrorKind::ExpectedRingDirection);
+        let rotation = match self.next() {
+            Some('L') => return Ok(RingDirection::Left),
+            Some('R') => return Ok(RingDirection::Right),
+            Some('-') => {
+                if self.next() != Some('C') {
+                    return Err(error);
+                }
+                match self.next() {
+                    Some('W') => Rotation::Clockwise,
+                    Some('C') => match self.next() {
+                        Some('W') => Rotation::CounterClockwise,
+                        _ => return Err(error),
+                    },
+                    _ => return Err(error),
+                }
+            }
+            _ => return Err(error),
+        };
+        Ok(R::direction(rotation, self.side))
+    }
+
+    fn pieces(&mut self) -> Result<NonEmptyIndexSet<VerticalPieces>, ParseError> {
+        let error = self.error(ParseErrorKind::ExpectedOpenBrace);
+        if self.next() != Some('{') {
+            return Err(error);
+        }
+
+        let mut pieces = Vec::new();
+        loop {
+            self.skip_blanks();
+            let error = self.error(ParseErrorKind::ExpectedPiece);
+            let c = self.next_required()?;
+            let piece = match c {
+                'I' => VerticalPieces::InnerRing,
+                'O' => VerticalPieces::OuterRing,
+                'V' => VerticalPieces::ValveHalf,
+                _ => return Err(error),
+            };
+            if pieces.contains(&piece) {
+                return Err(ParseError {
+                    kind: ParseErrorKind::DuplicatePiece(c),
+                    ..error
+                });
+            }
+            pieces.push(piece);
+
+            self.skip_blanks();
+            let error = self.error(ParseErrorKind::ExpectedSeparator);
+            match self.next_required()? {
+                ',' => {}
+                '}' => break,
+                _ => return Err(error),
+            }
+        }
+
+        // Put the pieces in the same order as [`Move::iter`] so that equal moves compare equal
+        Ok(
+            NonEmptyIndexSet::from_iterator(VerticalPieces::iter().filter(|p| pieces.contains(p)))
+                .unwrap(),
+        )
+    }
+
+    fn muv(&mut self) -> Result<Move, ParseError> {
+        let error = self.error(ParseErrorKind::UnexpectedEnd);
+        let c = self.next_required()?;
+        Ok(match c {
+            'I' => Move::InnerRingRotate(self.ring_direction::<InnerRing>()?),
+            'O' => Move::OuterRingRotate(self.ring_direction::<OuterRing>()?),
+            'U' | 'D' => Move::Vertical {
+                direction: if c == 'U' {
+                    VerticalDirection::Up
+                } else {
+                    VerticalDirection::Down
+                },
+                pieces: self.pieces()?,
+            },
+            _ => {
+                return Err(ParseError {
+                    kind: ParseErrorKind::ExpectedMove(c),
+                    ..error
+                })
+            }
+        })
+    }
+
+    /// An optional repeat count following a move, which defaults to one.
+    fn count(&mut self) -> Result<usize, ParseError> {
+        let error = self.error(ParseErrorKind::InvalidCount);
+        let mut digits = String::new();
+        while let Some(c) = self.peek().filter(char::is_ascii_digit) {
+            digits.push(c);
+            self.next();
+        }
+
+        if digits.is_empty() {
+            return Ok(1);
+        }
+        match digits.parse() {
+            Ok(n) if (1..=MAX_COUNT).contains(&n) => Ok(n),
+            _ => Err(error),
+        }
+    }
+}
+
+impl FromStr for Move {
+    type Err = ParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parser = Parser::new(s, ViewingSide::Hanayama);
+        let muv = parser.muv()?;
+        if parser.peek().is_some() {
+            return Err(parser.error(ParseErrorKind::Trailing));
+        }
+        Ok(muv)
+    }
+}
+
+/// Parses a whole sequence of moves in the notation of [`Move::as_str`].
+///
+/// Each move may be followed by a repeat count, such as `IL2`, and moves may be separated
+/// by whitespace and `#` comments. Ring moves may also be written as rotations seen from the
+/// HANAYAMA face like in the guide, such as `I-CCW2`.
+pub fn parse_moves(s: &str) -> Result<Vec<Move>, ParseError> {
+    parse_moves_seen_from(s, ViewingSide::Hanayama)
+}
+
+/// Like [`parse_moves`], but with rotations seen from the given side.
+pub fn parse_moves_seen_from(s: &str, side: ViewingSide) -> Result<Vec<Move>, ParseError> {
+    Ok(parse_moves_with_positions(s, side)?
+        .into_iter()
+        .map(|(muv, _, _)| muv)
+        .collect())
+}
+
+/// Like [`parse_moves_seen_from`], but also returns the line and column at which each move
+/// starts.
+pub fn parse_moves_with_positions(
+    s: &str,
+    side: ViewingSide,
+) -> Result<Vec<(Move, usize, usize)>, ParseError> {
+    let mut parser = Parser::new(s, side);
+    let mut moves = Vec::new();
+
+    loop {
+        parser.skip_blanks();
+        if parser.peek().is_none() {
+            break;
+        }
+
+        let (line, column) = (parser.line, parser.column);
+        let muv = parser.muv()?;
+        let count = parser.count()?;
+        moves.extend(std::iter::repeat_n((muv, line, column), count));
+    }
+
+    Ok(moves)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn moves_have_positions() {
+        let moves = parse_moves_with_positions("IL2 # two\n  D{V, I}", ViewingSide::Hanayama);
+        let down = Move::Vertical {
+            direction: VerticalDirection::Down,
+            pieces: NonEmptyIndexSet::from_iterator([
+                VerticalPieces::InnerRing,
+                VerticalPieces::ValveHalf,
+            ])
+            .unwrap(),
+        };
+        assert_eq!(
+            moves,
+            Ok(vec![
+                (Move::InnerRingRotate(RingDirection::Left), 1, 1),
+                (Move::InnerRingRotate(RingDirection::Left), 1, 1),
+                (down, 2, 3),
+            ])
+        );
+    }
+
+    #[test]
+    fn errors_have_positions() {
+        use ParseErrorKind::*;
+
+        for (s, line, column, kind) in [
+            ("X", 1, 1, ExpectedMove('X')),
+            ("IL OX", 1, 5, ExpectedRingDirection),
+            ("I-CX", 1, 2, ExpectedRingDirection),
+            ("U I", 1, 2, ExpectedOpenBrace),
+            ("D{Q}", 1, 3, ExpectedPiece),
+            ("D{I V}", 1, 5, ExpectedSeparator),
+            ("D{I, I}", 1, 6, DuplicatePiece('I')),
+            ("IL0", 1, 3, InvalidCount),
+            ("IL\nOR11", 2, 3, InvalidCount),
+            ("IL999999999", 1, 3, InvalidCount),
+            ("# comment\nD{I,", 2, 5, UnexpectedEnd),
+        ] {
+            let error = ParseError { line, column, kind };
+            assert_eq!(parse_moves(s), Err(error), "{s:?}");
+        }
+
+        assert_eq!(
+            "ILL".parse::<Move>(),
+            Err(ParseError {
+                line: 1,
+                column: 3,
+                kind: Trailing
+            })
+        );
+        assert_eq!(
+            parse_moves(&format!("IL{MAX_COUNT}")).unwrap().len(),
+            MAX_COUNT
+        );
+    }
+
+    #[test]
+    fn rotations_round_trip() {
+        for side in [ViewingSide::Hanayama, ViewingSide::Valve] {
+            for muv in Move::iter() {
+                let name = muv.name(Some(side));
+                assert_eq!(parse_moves_seen_from(&name, side), Ok(vec![muv]), "{name}");
+            }
+        }
+    }
+
+    #[test]
+    fn rotations_match_the_guide() {
+        // The first steps of the disassembly, which the guide describes from the HANAYAMA face
+        assert_eq!(parse_moves("I-CCW2 O-CCW O-CW2"), parse_moves("IL2 OR OL2"));
+        assert_eq!(
+            parse_moves_seen_from("I-CW2 O-CW O-CCW2", ViewingSide::Valve),
+            parse_moves("IL2 OR OL2")
+        );
+    }
+}
@@ -0,0 +1,271 @@
+//! The procedures and figures in the Valve section of the guide, which are checked against the
+//! model by the tests, drawn and written out for the guide by `--figures`, and walked through by
+//! `--tutorial`.
+
+use std::io::Write;
+
+use itertools::Itertools;
+use serde::Deserialize;
+
+use crate::{
+    notation::parse_moves,
+    valve::{ModPoint, Move, Piece, ValveState},
+};
+
+const GUIDE_PROCEDURES: &str = include_str!("../procedures/guide.toml");
+
+/// The reassembly in the guide starts with steps that fit the loose pieces together, which come
+/// before its steps that are moves of the model.
+pub const REASSEMBLY_FITTING_STEPS: usize = 3;
+
+#[derive(Deserialize, Clone, Copy)]
+#[serde(deny_unknown_fields)]
+pub struct StateSpec {
+    inner_ring: [i32; 2],
+    outer_ring: [i32; 2],
+    valve_half: i32,
+}
+impl From<StateSpec> for ValveState {
+    fn from(value: StateSpec) -> Self {
+        Self {
+            inner_ring: ModPoint::new(value.inner_ring[0], value.inner_ring[1]),
+            outer_ring: ModPoint::new(value.outer_ring[0], value.outer_ring[1]),
+            valve_half_y: value.valve_half,
+        }
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Step {
+    pub figure: String,
+    /// What to do in the step as written in the guide, one sentence per line.
+    pub text: String,
+    pub moves: String,
+    pub state: StateSpec,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Procedure {
+    pub start: StateSpec,
+    pub steps: Vec<Step>,
+}
+impl Procedure {
+    /// Writes the steps as items of a LaTeX list, each followed by its figure, for `\input`
+    /// into the guide.
+    pub fn write_tex(&self, writer: &mut impl Write) -> std::io::Result<()> {
+        for step in self.steps.iter() {
+            for (i, line) in step
+                .text
+                .lines()
+                .filter(|l| !l.trim().is_empty())
+                .enumerate()
+            {
+                let indent = if i == 0 {
+                    "      \\item "
+                } else {
+                    "            "
+                };
+                writeln!(writer, "{indent}{}", tex_macros(&escape_tex(line.trim())))?;
+            }
+            writeln!(writer, "            \\valvediagram{{{}}}", step.figure)?;
+        }
+        Ok(())
+    }
+}
+
+/// Escapes the characters of plain text that LaTeX would otherwise treat specially.
+fn escape_tex(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '%' | '&' | '#' | '$' | '_' | '{' | '}' => {
+                escaped.push('\\');
+                escaped.push(c);
+            }
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// The names that the guide writes with its own macros, so that they are formatted the same as
+/// in the rest of it.
+const TEX_MACROS: [(&str, &str); 3] = [
+    ("counter-clockwise", "\\cc{}"),
+    ("Hanayama half", "\\hhalf{}"),
+    ("Valve half", "\\vhalf{}"),
+];
+
+fn tex_macros(text: &str) -> String {
+    TEX_MACROS
+        .iter()
+        .fold(text.to_string(), |text, (name, command)| {
+            text.replace(name, command)
+        })
+}
+
+/// A figure that is not the end of a step.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Figure {
+    pub figure: String,
+    /// The pieces shown, or all of them if not given.
+    pub pieces: Option<Vec<Piece>>,
+    pub state: StateSpec,
+    /// The pieces moved by the step before the figure, if any, as they move in the figure, where
+    /// the Hanayama half is fixed.
+    #[serde(skip)]
+    pub moved: Vec<Piece>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Guide {
+    pub disassembly: Procedure,
+    pub reassembly: Procedure,
+    pub figures: Vec<Figure>,
+}
+impl Guide {
+    pub fn get() -> Self {
+        toml::from_str(GUIDE_PROCEDURES).expect("built-in guide procedures are valid")
+    }
+
+    /// Every figure in the guide, including the ends of the steps of both procedures.
+    pub fn all_figures(self) -> Vec<Figure> {
+        self.disassembly
+            .steps
+            .into_iter()
+            .chain(self.reassembly.steps)
+            .map(|step| Figure {
+                moved: parse_moves(&step.moves)
+                    .expect("built-in guide moves are valid")
+                    .iter()
+                    .flat_map(Move::pieces)
+                    .unique()
+                    .collect(),
+                figure: step.figure,
+                pieces: None,
+                state: step.state,
+            })
+            .chain(self.figures)
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::valve::Valve;
+
+    const DISASSEMBLY_SCRIPT: &str = include_str!("../procedures/disassembly.txt");
+
+    /// Performs every step of a procedure, checking that each move is possible and that
+    /// each step ends in the expected state, and returns the final state.
+    fn check_procedure(procedure: Procedure) -> ValveState {
+        let mut valve = Valve::from(ValveState::from(procedure.start));
+
+        for step in procedure.steps {
+            for muv in parse_moves(&step.moves).unwrap() {
+                let from = valve.state();
+                if let Err(e) = valve.make_move(&muv) {
+                    panic!("{}: {e} from {from}", step.figure);
+                }
+            }
+
+            assert_eq!(
+                valve.state(),
+                step.state.into(),
+                "{} ends in the wrong state",
+                step.figure
+            );
+        }
+
+        valve.state()
+    }
+
+    #[test]
+    fn disassembly() {
+        let guide = Guide::get();
+        assert_eq!(
+            ValveState::from(guide.disassembly.start),
+            ValveState::default()
+        );
+
+        let end = check_procedure(guide.disassembly);
+        assert!(Valve::from(end).is_separated());
+    }
+
+    #[test]
+    fn reassembly() {
+        let guide = Guide::get();
+        let disassembled = guide.disassembly.steps.last().unwrap().state;
+        assert_eq!(
+            ValveState::from(guide.reassembly.start),
+            ValveState::from(disassembled)
+        );
+
+        assert_eq!(check_procedure(guide.reassembly), ValveState::default());
+    }
+
+    #[test]
+    fn only_the_end_of_the_disassembly_is_apart() {
+        let guide = Guide::get();
+        let (last, steps) = guide.disassembly.steps.split_last().unwrap();
+        assert_eq!(last.figure, "apart-09");
+        assert!(Valve::from(ValveState::from(last.state)).is_separated());
+
+        let together = std::iter::once(guide.disassembly.start)
+            .chain(steps.iter().map(|step| step.state))
+            .chain(guide.reassembly.steps.iter().map(|step| step.state));
+        for state in together {
+            let state = ValveState::from(state);
+            assert!(!Valve::from(state).is_separated(), "{state} is apart");
+        }
+    }
+
+    #[test]
+    fn steps_use_the_guide_macros() {
+        for procedure in [Guide::get().disassembly, Guide::get().reassembly] {
+            let mut tex = Vec::new();
+            procedure.write_tex(&mut tex).unwrap();
+            let tex = String::from_utf8(tex).unwrap();
+
+            for (name, command) in TEX_MACROS {
+                assert!(!tex.contains(name), "{name} is written without {command}");
+            }
+            assert!(tex.contains("\\hhalf{}") && tex.contains("\\vhalf{}"));
+        }
+    }
+
+    #[test]
+    fn figures_mark_every_piece_moved_by_the_step() {
+        let figures = Guide::get().all_figures();
+        let moved = |name: &str| {
+            let figure = figures.iter().find(|f| f.figure == name).unwrap();
+            figure.moved.clone()
+        };
+
+        // The outer ring is rotated and then the Hanayama half drops, which in the figure is
+        // everything else rising
+        assert_eq!(
+            moved("apart-02"),
+            [Piece::OuterRing, Piece::InnerRing, Piece::ValveHalf]
+        );
+        assert_eq!(moved("apart-04"), [Piece::OuterRing, Piece::ValveHalf]);
+        assert_eq!(moved("halves-00"), []);
+    }
+
+    #[test]
+    fn disassembly_script() {
+        let mut valve = Valve::default();
+        for muv in parse_moves(DISASSEMBLY_SCRIPT).unwrap() {
+            assert!(valve.can_move(&muv), "{} is not possible", muv.as_str());
+            valve.make_move_unchecked(&muv);
+        }
+
+        let disassembled = Guide::get().disassembly.steps.last().unwrap().state;
+        assert_eq!(valve.state(), disassembled.into());
+    }
+}
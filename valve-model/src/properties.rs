@@ -0,0 +1,117 @@
+//! Property tests over random sequences of legal moves, which undo and redo rely on.
+
+use proptest::prelude::*;
+use undo::{Edit, Record};
+
+use crate::{
+    figure::{write_png, write_svg, write_tikz},
+    layout::{top_view_cells, valve_cells},
+    valve::{Collisions, Move, Piece, Step, Valve, ValveState},
+};
+
+/// Every state along a walk of legal moves from the starting position, each chosen by an index
+/// into the moves possible at that point, along with the move made from it.
+fn walk(choices: &[usize]) -> Vec<(ValveState, Move)> {
+    let mut valve = Valve::default();
+    let mut steps = Vec::new();
+
+    for &choice in choices {
+        let moves: Vec<_> = Move::iter().filter(|m| valve.can_move(m)).collect();
+        if moves.is_empty() {
+            break;
+        }
+        let muv = moves[choice % moves.len()].clone();
+
+        steps.push((valve.state(), muv.clone()));
+        valve.make_move(&muv).unwrap();
+    }
+
+    steps
+}
+
+fn choices() -> impl Strategy<Value = Vec<usize>> {
+    prop::collection::vec(any::<usize>(), 0..48)
+}
+
+proptest! {
+    #[test]
+    fn neg_restores_state(choices in choices()) {
+        for (state, muv) in walk(&choices) {
+            let mut valve = Valve::from(state);
+            valve.make_move(&muv).unwrap();
+            valve.make_move(&-muv).unwrap();
+            prop_assert_eq!(valve.state(), state);
+        }
+    }
+
+    #[test]
+    fn undo_matches_inverse(choices in choices()) {
+        for (state, mut muv) in walk(&choices) {
+            let mut edited = Valve::from(state);
+            muv.edit(&mut edited);
+
+            let mut inverse = edited.clone();
+            inverse.make_move_unchecked(&-muv.clone());
+
+            muv.undo(&mut edited);
+            prop_assert_eq!(edited.state(), inverse.state());
+            prop_assert_eq!(edited.state(), state);
+        }
+    }
+
+    #[test]
+    fn can_move_is_symmetric(choices in choices()) {
+        for (state, muv) in walk(&choices) {
+            let mut valve = Valve::from(state);
+            valve.make_move(&muv).unwrap();
+            prop_assert!(
+                valve.can_move(&-muv.clone()),
+                "{} cannot be undone from {}",
+                muv.as_str(),
+                valve.state()
+            );
+        }
+    }
+
+    #[test]
+    fn is_blocked_matches_collisions(choices in choices()) {
+        for (state, _) in walk(&choices) {
+            let valve = Valve::from(state);
+            for muv in Move::iter() {
+                prop_assert_eq!(valve.is_blocked(&muv), valve.collisions(&muv) != Collisions::default());
+            }
+        }
+    }
+
+    #[test]
+    fn record_undoes_and_redoes_everything(choices in choices()) {
+        let walk = walk(&choices);
+        let mut valve = Valve::default();
+        let mut record = Record::new();
+        for (_, muv) in walk.iter() {
+            record.edit(&mut valve, Step::from(muv.clone()));
+        }
+        let end = valve.state();
+
+        while record.undo(&mut valve).is_some() {}
+        prop_assert_eq!(valve.state(), ValveState::default());
+
+        while record.redo(&mut valve).is_some() {}
+        prop_assert_eq!(valve.state(), end);
+    }
+
+    #[test]
+    fn rendering_does_not_panic(choices in choices()) {
+        let mut valve = Valve::default();
+        for (_, muv) in walk(&choices) {
+            valve.make_move(&muv).unwrap();
+        }
+
+        let cells = valve_cells(&valve);
+        let mut out = Vec::new();
+        write_png(&mut out, &cells, &[Piece::InnerRing]).unwrap();
+        write_svg(&mut out, &cells, &[Piece::ValveHalf]).unwrap();
+        write_tikz(&mut out, &cells, &[Piece::OuterRing]).unwrap();
+        top_view_cells(&valve);
+    }
+}
@@ -0,0 +1,204 @@
+//! Putting the puzzle back together from its loose pieces, which starts by fitting the inner
+//! ring and the half rings together and sliding the outer ring onto them before any moves of
+//! the model are possible.
+
+use bare_metal_modulo::MNum;
+use itertools::Itertools;
+use non_empty_collections::NonEmptyIndexSet;
+
+use crate::{
+    solver,
+    valve::{
+        ModPoint, Move, Piece, Valve, ValveState, VerticalDirection, VerticalPieces,
+        VALVE_SECTION_HEIGHT, VALVE_SECTION_WIDTH,
+    },
+};
+
+/// A way of fitting the loose pieces together, which ends in a state of the model with every
+/// piece within its travel.
+///
+/// The inner ring and the half rings are fitted together from the sides, so they can be at any
+/// layers and the inner ring at any segment as long as none of their extrusions overlap. The
+/// outer ring is then slid on from above or below at one of its segments, and can stop at any
+/// layer it gets to without anything in its way, such as resting on the Valve half as in the
+/// guide. Any other state, such as one with the outer ring locked between extrusions of the
+/// half rings, can only be got to by moves of the model after that.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Insertion {
+    /// The direction the outer ring is slid in, which is down when it starts above the others.
+    pub direction: VerticalDirection,
+    pub state: ValveState,
+}
+impl std::fmt::Display for Insertion {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "fit the inner ring at segment {} and layer {} with the Valve half at layer {}, then \
+             slide the outer ring {} onto them at segment {} to layer {}",
+            self.state.inner_ring.x.a(),
+            self.state.inner_ring.y - 1,
+            self.state.valve_half_y,
+            match self.direction {
+                VerticalDirection::Up => "up",
+                VerticalDirection::Down => "down",
+            },
+            self.state.outer_ring.x.a(),
+            self.state.outer_ring.y - 1
+        )
+    }
+}
+
+/// Every way of fitting the loose pieces together.
+pub fn insertions() -> Vec<Insertion> {
+    let travel = |piece: Piece| piece.travel().collect::<Vec<_>>();
+    let mut insertions = Vec::new();
+
+    for (inner_x, inner_top, valve_y) in itertools::iproduct!(
+        0..VALVE_SECTION_WIDTH,
+        travel(Piece::InnerRing),
+        travel(Piece::ValveHalf)
+    ) {
+        let inner_ring = ModPoint::new(inner_x, inner_top + 1);
+
+        for direction in [VerticalDirection::Down, VerticalDirection::Up] {
+            for outer_x in 0..VALVE_SECTION_WIDTH {
+                // Start with the outer ring clear of both half rings
+                let outer_top = match direction {
+                    VerticalDirection::Down => valve_y.min(0) - VALVE_SECTION_HEIGHT,
+                    VerticalDirection::Up => valve_y.max(0) + VALVE_SECTION_HEIGHT,
+                };
+                let valve = Valve::from(ValveState {
+                    inner_ring,
+                    outer_ring: ModPoint::new(outer_x, outer_top + 1),
+                    valve_half_y: valve_y,
+                });
+
+                insertions.extend(
+                    slide_outer_ring(valve, direction)
+                        .into_iter()
+                        .map(|state| Insertion { direction, state }),
+                );
+            }
+        }
+    }
+
+    // Sliding the outer ring down or up can get it to the same place
+    insertions.into_iter().unique_by(|i| i.state).collect()
+}
+
+/// Every state within the outer ring's travel that it can be slid to until something is in its
+/// way, as long as the other pieces fit together.
+fn slide_outer_ring(mut valve: Valve, direction: VerticalDirection) -> Vec<ValveState> {
+    let muv = Move::Vertical {
+        direction,
+        pieces: NonEmptyIndexSet::new(VerticalPieces::OuterRing),
+    };
+    let travel = Piece::OuterRing.travel();
+    let mut states = Vec::new();
+
+    loop {
+        if travel.contains(&valve.displacement(Piece::OuterRing)) {
+            if valve.check_invariants().is_err() {
+                break;
+            }
+            states.push(valve.state());
+        } else if !states.is_empty() {
+            // Slid all the way through
+            break;
+        }
+        if valve.is_blocked(&muv) {
+            break;
+        }
+        valve.make_move_unchecked(&muv);
+    }
+
+    states
+}
+
+/// The shortest way back to the starting position from the loose pieces.
+pub struct Reassembly {
+    pub insertion: Insertion,
+    pub moves: Vec<Move>,
+}
+
+/// Searches from every way of fitting the loose pieces together at once for the fewest moves
+/// back to the starting position.
+pub fn solve() -> Option<Reassembly> {
+    let insertions = insertions();
+    let start = ValveState::default();
+
+    let (from, moves) = solver::search(insertions.iter().map(|i| i.state), |valve| {
+        valve.state() == start
+    })?;
+    Some(Reassembly {
+        insertion: *insertions.iter().find(|i| i.state == from)?,
+        moves,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{notation::parse_moves, procedures::Guide, valve::RingDirection};
+
+    #[test]
+    fn reassembly_is_no_longer_than_the_guide() {
+        for insertion in insertions() {
+            Valve::from(insertion.state).check_invariants().unwrap();
+        }
+
+        let reassembly = solve().unwrap();
+        let mut valve = Valve::from(reassembly.insertion.state);
+        for muv in reassembly.moves.iter() {
+            valve.make_move(muv).unwrap();
+        }
+        assert_eq!(valve.state(), ValveState::default());
+
+        // The guide rests the outer ring on the Valve half and turns it halfway around to where
+        // the rest of its steps start from
+        let half_turn = VALVE_SECTION_WIDTH / 2;
+        let guide = Guide::get().reassembly;
+        let fitted = ValveState::from(guide.start);
+        let placed = ValveState {
+            outer_ring: fitted.outer_ring + ModPoint::new(half_turn, 0),
+            ..fitted
+        };
+        assert!(insertions().iter().any(|i| i.state == placed));
+
+        let mut valve = Valve::from(placed);
+        for _ in 0..half_turn {
+            valve
+                .make_move(&Move::OuterRingRotate(RingDirection::Left))
+                .unwrap();
+        }
+        assert_eq!(valve.state(), fitted);
+
+        let guide_moves = guide
+            .steps
+            .iter()
+            .map(|step| parse_moves(&step.moves).unwrap().len())
+            .sum::<usize>();
+        assert!(reassembly.moves.len() <= half_turn as usize + guide_moves);
+    }
+
+    #[test]
+    fn outer_ring_cannot_be_slid_past_extrusions() {
+        let fitted = ValveState::from(Guide::get().reassembly.start);
+        let insertions = insertions();
+
+        // Once turned into place, the outer ring is held between extrusions of both halves
+        assert!(!insertions.iter().any(|i| i.state == fitted));
+
+        // Without turning it, sliding it down stops before the Valve half
+        let blocked = ValveState {
+            outer_ring: fitted.outer_ring + ModPoint::new(0, -1),
+            ..fitted
+        };
+        assert!(!insertions.iter().any(|i| i.state == blocked));
+        let stopped = ValveState {
+            outer_ring: fitted.outer_ring + ModPoint::new(0, -2),
+            ..fitted
+        };
+        assert!(insertions.iter().any(|i| i.state == stopped));
+    }
+}
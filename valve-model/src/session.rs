@@ -0,0 +1,94 @@
+//! Saving the steps taken in the model to a file so that an exploration can be resumed or
+//! shared later, with both undo and redo intact.
+
+use itertools::Itertools;
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+use thiserror::Error;
+use undo::Record;
+
+use crate::{
+    notation::{parse_moves, ParseError},
+    valve::{MoveError, Step, Valve},
+};
+
+#[derive(Error, Debug)]
+pub enum SessionError {
+    #[error("could not access the session file")]
+    Io(#[from] std::io::Error),
+    #[error("could not parse the session file")]
+    Parse(#[from] toml::de::Error),
+    #[error("could not write the session")]
+    Serialize(#[from] toml::ser::Error),
+    #[error("invalid moves in step {step}")]
+    Notation {
+        step: usize,
+        #[source]
+        source: ParseError,
+    },
+    #[error("could not make step {step}")]
+    IllegalMove {
+        step: usize,
+        #[source]
+        source: MoveError,
+    },
+    #[error("the position {position} is past the last of the {steps} steps")]
+    Position { position: usize, steps: usize },
+}
+
+/// Every step from the starting position in move notation, along with how many of them had
+/// been made, with the rest having been undone.
+#[derive(Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct SessionFile {
+    position: usize,
+    steps: Vec<String>,
+}
+
+pub fn save(path: &Path, record: &Record<Step>) -> Result<(), SessionError> {
+    let file = SessionFile {
+        position: record.head(),
+        steps: record
+            .entries()
+            .map(|entry| entry.get().0.iter().map(|m| m.as_str()).join(" "))
+            .collect(),
+    };
+
+    std::fs::write(path, toml::to_string(&file)?)?;
+    Ok(())
+}
+
+/// Replays the steps of a session from the starting position, checking that every move is
+/// possible, and then undoes those past its position.
+pub fn load(path: &Path) -> Result<(Valve, Record<Step>), SessionError> {
+    let file: SessionFile = toml::from_str(&std::fs::read_to_string(path)?)?;
+    if file.position > file.steps.len() {
+        return Err(SessionError::Position {
+            position: file.position,
+            steps: file.steps.len(),
+        });
+    }
+
+    let mut valve = Valve::default();
+    let mut record = Record::new();
+    for (i, text) in file.steps.iter().enumerate() {
+        let moves = parse_moves(text).map_err(|source| SessionError::Notation {
+            step: i + 1,
+            source,
+        })?;
+
+        let mut next = valve.clone();
+        for muv in moves.iter() {
+            next.make_move(muv)
+                .map_err(|source| SessionError::IllegalMove {
+                    step: i + 1,
+                    source,
+                })?;
+        }
+
+        record.edit(&mut valve, Step(moves));
+    }
+    record.go_to(&mut valve, file.position);
+
+    Ok((valve, record))
+}
@@ -0,0 +1,55 @@
+use std::collections::{hash_map::Entry, HashMap, VecDeque};
+
+use crate::valve::{Move, Valve, ValveState};
+
+/// Performs a breadth-first search from `start` for the shortest sequence of
+/// moves that separates the puzzle, returning [`None`] if this is not possible.
+pub fn solve(start: &Valve) -> Option<Vec<Move>> {
+    search([start.state()], |valve| valve.is_separated()).map(|(_, moves)| moves)
+}
+
+/// Performs a breadth-first search from all of the `starts` at once for the shortest sequence
+/// of moves to a goal, returning the start it was made from along with the moves.
+pub fn search(
+    starts: impl IntoIterator<Item = ValveState>,
+    is_goal: impl Fn(&Valve) -> bool,
+) -> Option<(ValveState, Vec<Move>)> {
+    let mut parents: HashMap<ValveState, Option<(ValveState, Move)>> = HashMap::new();
+    let mut queue = VecDeque::new();
+
+    for start in starts {
+        if let Entry::Vacant(e) = parents.entry(start) {
+            e.insert(None);
+            queue.push_back(start);
+        }
+    }
+
+    while let Some(state) = queue.pop_front() {
+        let valve = Valve::from(state);
+
+        if is_goal(&valve) {
+            // Walk back up the tree to recover the moves
+            let mut moves = Vec::new();
+            let mut state = state;
+            while let Some((parent, muv)) = parents[&state].clone() {
+                moves.push(muv);
+                state = parent;
+            }
+            moves.reverse();
+
+            return Some((state, moves));
+        }
+
+        for muv in Move::iter().filter(|m| valve.can_move(m)) {
+            let mut next = valve.clone();
+            next.make_move_unchecked(&muv);
+
+            if let Entry::Vacant(e) = parents.entry(next.state()) {
+                e.insert(Some((state, muv)));
+                queue.push_back(next.state());
+            }
+        }
+    }
+
+    None
+}
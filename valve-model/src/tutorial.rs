@@ -0,0 +1,230 @@
+//! Walks through a procedure of the guide one step at a time, following along as its moves are
+//! made and telling apart the moves that are not the next one.
+
+use clap::ValueEnum;
+use itertools::Itertools;
+use thiserror::Error;
+
+use crate::{
+    notation::parse_moves,
+    procedures::{Guide, REASSEMBLY_FITTING_STEPS},
+    valve::{Move, MoveError, Valve, ValveState},
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum ProcedureKind {
+    /// Taking the puzzle apart from the starting position.
+    Disassembly,
+    /// Putting the puzzle back together once the loose pieces have been fitted together.
+    Reassembly,
+}
+impl ProcedureKind {
+    pub fn name(&self) -> &'static str {
+        match self {
+            ProcedureKind::Disassembly => "Disassembly",
+            ProcedureKind::Reassembly => "Reassembly",
+        }
+    }
+}
+
+/// A procedure of the guide that cannot be followed in the model, such as with a geometry
+/// loaded with `--geometry` that differs from the puzzle's.
+#[derive(Error, Debug)]
+pub enum TutorialError {
+    #[error("could not make move {muv} of step {step} of the {} procedure", .kind.name())]
+    IllegalMove {
+        kind: ProcedureKind,
+        step: usize,
+        muv: usize,
+        #[source]
+        source: MoveError,
+    },
+}
+
+pub struct TutorialStep {
+    /// The number of the step in the guide.
+    pub number: usize,
+    pub text: String,
+    pub moves: Vec<Move>,
+}
+
+/// How a state compares to the moves of the procedure made so far.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Progress {
+    /// No moves were made.
+    Same,
+    /// The next moves were made, which may have finished the step.
+    Forward { finished_step: bool },
+    /// Moves were taken back, such as by undoing them.
+    Back,
+    /// A move was made that is not in the procedure.
+    Wrong,
+}
+
+pub struct Tutorial {
+    pub kind: ProcedureKind,
+    pub steps: Vec<TutorialStep>,
+    /// Every move of the procedure in order, along with the index of the step it is in.
+    moves: Vec<(usize, Move)>,
+    /// The state before every move of the procedure, followed by the state it ends in.
+    states: Vec<ValveState>,
+    /// How many moves of the procedure have been made.
+    position: usize,
+}
+impl Tutorial {
+    pub fn new(kind: ProcedureKind) -> Result<Self, TutorialError> {
+        let guide = Guide::get();
+        let (procedure, first) = match kind {
+            ProcedureKind::Disassembly => (guide.disassembly, 1),
+            ProcedureKind::Reassembly => (guide.reassembly, REASSEMBLY_FITTING_STEPS + 1),
+        };
+
+        let steps = procedure
+            .steps
+            .into_iter()
+            .enumerate()
+            .map(|(i, step)| TutorialStep {
+                number: first + i,
+                text: step
+                    .text
+                    .lines()
+                    .map(str::trim)
+                    .join(" ")
+                    .trim()
+                    .to_string(),
+                moves: parse_moves(&step.moves).expect("built-in guide moves are valid"),
+            })
+            .collect::<Vec<_>>();
+
+        let moves = steps
+            .iter()
+            .enumerate()
+            .flat_map(|(i, step)| step.moves.iter().map(move |m| (i, m.clone())))
+            .collect::<Vec<_>>();
+
+        let mut valve = Valve::from(ValveState::from(procedure.start));
+        let mut states = vec![valve.state()];
+        for step in steps.iter() {
+            for (i, muv) in step.moves.iter().enumerate() {
+                valve
+                    .make_move(muv)
+                    .map_err(|source| TutorialError::IllegalMove {
+                        kind,
+                        step: step.number,
+                        muv: i + 1,
+                        source,
+                    })?;
+                states.push(valve.state());
+            }
+        }
+
+        Ok(Self {
+            kind,
+            steps,
+            moves,
+            states,
+            position: 0,
+        })
+    }
+
+    /// The state the procedure starts in.
+    pub fn start(&self) -> ValveState {
+        self.states[0]
+    }
+
+    /// The step that the next move is in, unless the procedure is finished.
+    pub fn step(&self) -> Option<&TutorialStep> {
+        self.moves.get(self.position).map(|(i, _)| &self.steps[*i])
+    }
+
+    pub fn next_move(&self) -> Option<&Move> {
+        self.moves.get(self.position).map(|(_, muv)| muv)
+    }
+
+    /// How many moves of the current step have been made.
+    pub fn moves_made_in_step(&self) -> usize {
+        match self.moves.get(self.position) {
+            Some((step, _)) => self.moves[..self.position]
+                .iter()
+                .rev()
+                .take_while(|(i, _)| i == step)
+                .count(),
+            None => 0,
+        }
+    }
+
+    /// The states from the start of the current step to its end, to show how it is done.
+    pub fn step_states(&self) -> &[ValveState] {
+        let start = self.position - self.moves_made_in_step();
+        let len = self.step().map_or(0, |step| step.moves.len());
+        &self.states[start..=start + len]
+    }
+
+    /// Follows along to a state that moves were made to, which is the next state of the
+    /// procedure that it is in if any, or else the last one before the moves made so far.
+    pub fn follow(&mut self, state: ValveState) -> Progress {
+        if state == self.states[self.position] {
+            return Progress::Same;
+        }
+
+        let step = self.moves.get(self.position).map(|(i, _)| *i);
+        if let Some(i) = self.states[self.position..]
+            .iter()
+            .position(|s| *s == state)
+        {
+            self.position += i;
+            Progress::Forward {
+                finished_step: self.moves.get(self.position).map(|(i, _)| *i) != step,
+            }
+        } else if let Some(i) = self.states[..self.position]
+            .iter()
+            .rposition(|s| *s == state)
+        {
+            self.position = i;
+            Progress::Back
+        } else {
+            Progress::Wrong
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tutorials_follow_the_guide() {
+        for kind in [ProcedureKind::Disassembly, ProcedureKind::Reassembly] {
+            let mut tutorial = Tutorial::new(kind).unwrap();
+            let mut valve = Valve::from(tutorial.start());
+
+            // A wrong move is not followed
+            let wrong = Move::iter()
+                .filter(|m| valve.can_move(m))
+                .find(|m| Some(m) != tutorial.next_move())
+                .unwrap();
+            let mut wrong_valve = valve.clone();
+            wrong_valve.make_move(&wrong).unwrap();
+            assert_eq!(tutorial.follow(wrong_valve.state()), Progress::Wrong);
+
+            let mut steps = 0;
+            while let Some(muv) = tutorial.next_move().cloned() {
+                assert_eq!(
+                    tutorial.step_states()[tutorial.moves_made_in_step()],
+                    valve.state()
+                );
+                valve.make_move(&muv).unwrap();
+                if let Progress::Forward { finished_step } = tutorial.follow(valve.state()) {
+                    steps += usize::from(finished_step);
+                } else {
+                    panic!("{} was not followed", muv.as_str());
+                }
+            }
+            assert_eq!(steps, tutorial.steps.len());
+
+            // Going back to the start is followed too
+            assert_eq!(tutorial.follow(tutorial.start()), Progress::Back);
+            assert_eq!(tutorial.moves_made_in_step(), 0);
+        }
+    }
+}
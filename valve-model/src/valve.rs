@@ -1,22 +1,24 @@
 use bare_metal_modulo::{MNum, ModNumC};
+use clap::ValueEnum;
 use euclid::{Point2D, Vector2D};
 use itertools::Itertools;
-use maplit::hashset;
 use non_empty_collections::NonEmptyIndexSet;
+use serde::Deserialize;
 use std::{
     borrow::Cow,
     collections::{HashMap, HashSet},
+    hash::{Hash, Hasher},
     marker::PhantomData,
-    ops::{AddAssign, SubAssign},
-    sync::LazyLock,
+    ops::{AddAssign, RangeInclusive, SubAssign},
 };
 use strum::IntoEnumIterator;
 use strum_macros::EnumIter;
+use thiserror::Error;
 use undo::Edit;
 
-use crate::Absolute;
+use crate::{geometry::Geometry, Absolute};
 
-#[derive(Debug, PartialEq, Eq)]
+#[derive(Debug)]
 pub struct ModPoint<U> {
     pub x: ModNumC<i32, 6>,
     pub y: i32,
@@ -45,6 +47,18 @@ impl<U> Clone for ModPoint<U> {
     }
 }
 impl<U> Copy for ModPoint<U> {}
+impl<U> PartialEq for ModPoint<U> {
+    fn eq(&self, other: &Self) -> bool {
+        self.x == other.x && self.y == other.y
+    }
+}
+impl<U> Eq for ModPoint<U> {}
+impl<U> Hash for ModPoint<U> {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.x.hash(state);
+        self.y.hash(state);
+    }
+}
 impl<U> From<ModPoint<U>> for Point2D<i32, U> {
     fn from(value: ModPoint<U>) -> Self {
         Self::new(value.x.a(), value.y)
@@ -85,96 +99,48 @@ impl<U> SubAssign for ModPoint<U> {
     }
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub struct Inside;
 pub type InsidePoint = Point2D<i32, Inside>;
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub struct Outside;
 pub type OutsidePoint = Point2D<i32, Outside>;
 
-#[derive(Default)]
+#[derive(Default, Clone)]
 pub struct HanayamaHalf;
 impl HanayamaHalf {
     pub fn inside_points(&self) -> &'static HashSet<InsidePoint> {
-        static HANAYAMA_HALF_INSIDE_POINTS: LazyLock<HashSet<InsidePoint>> = LazyLock::new(|| {
-            hashset! {
-                InsidePoint::new(0, 0),
-                InsidePoint::new(1, 0),
-                InsidePoint::new(2, 0),
-                InsidePoint::new(0, 1),
-                InsidePoint::new(0, 2),
-                InsidePoint::new(2, 3),
-                InsidePoint::new(0, 4),
-                InsidePoint::new(1, 4),
-                InsidePoint::new(2, 4),
-            }
-        });
-
-        &HANAYAMA_HALF_INSIDE_POINTS
+        &Geometry::get().hanayama_half.inside
     }
 
     pub fn outside_points(&self) -> &'static HashSet<OutsidePoint> {
-        static HANAYAMA_HALF_OUTSIDE_POINTS: LazyLock<HashSet<OutsidePoint>> =
-            LazyLock::new(|| {
-                hashset! {
-                    OutsidePoint::new(3, 0),
-                    OutsidePoint::new(4, 0),
-                    OutsidePoint::new(5, 0),
-                    OutsidePoint::new(5, 2),
-                    OutsidePoint::new(3, 4),
-                    OutsidePoint::new(4, 4),
-                    OutsidePoint::new(5, 4),
-                }
-            });
-
-        &HANAYAMA_HALF_OUTSIDE_POINTS
+        &Geometry::get().hanayama_half.outside
     }
 }
 
-#[derive(Default)]
+#[derive(Default, Clone)]
 pub struct ValveHalf {
     y: i32,
 }
 impl ValveHalf {
     pub fn inside_points(&self, shift: Option<i32>) -> HashSet<InsidePoint> {
-        static VALVE_HALF_INSIDE_POINTS: LazyLock<HashSet<Point2D<i32, ValveHalf>>> =
-            LazyLock::new(|| {
-                hashset! {
-                    Point2D::new(0, 0),
-                    Point2D::new(1, 0),
-                    Point2D::new(2, 0),
-                    Point2D::new(2, 2),
-                    Point2D::new(0, 4),
-                    Point2D::new(1, 4),
-                    Point2D::new(2, 4),
-                }
-            });
-
         let shift = shift.unwrap_or(0);
-        VALVE_HALF_INSIDE_POINTS
+        Geometry::get()
+            .valve_half
+            .inside
             .iter()
-            .map(|p| p.cast_unit() + Vector2D::new(3, self.y + shift))
+            .map(|p| *p + Vector2D::new(0, self.y + shift))
             .collect()
     }
 
     pub fn outside_points(&self, shift: Option<i32>) -> HashSet<OutsidePoint> {
-        static VALVE_HALF_OUTSIDE_POINTS: LazyLock<HashSet<Point2D<i32, ValveHalf>>> =
-            LazyLock::new(|| {
-                hashset! {
-                    Point2D::new(0, 0),
-                    Point2D::new(1, 0),
-                    Point2D::new(2, 0),
-                    Point2D::new(0, 2),
-                    Point2D::new(1, 2),
-                    Point2D::new(0, 4),
-                    Point2D::new(1, 4),
-                    Point2D::new(2, 4),
-                }
-            });
-
         let shift = shift.unwrap_or(0);
-        VALVE_HALF_OUTSIDE_POINTS
+        Geometry::get()
+            .valve_half
+            .outside
             .iter()
-            .map(|p| p.cast_unit() + Vector2D::new(0, self.y + shift))
+            .map(|p| *p + Vector2D::new(0, self.y + shift))
             .collect()
     }
 
@@ -186,10 +152,43 @@ impl ValveHalf {
 pub trait Ring: Sized {
     type Coordinates;
 
+    /// The direction that turns the ring clockwise as seen from the HANAYAMA face.
+    ///
+    /// The inside section is drawn as seen from inside the half rings and the outside section as
+    /// seen from outside, so this is right for the inner ring but left for the outer ring.
+    const CLOCKWISE: RingDirection;
+
+    /// The rotation of the ring when moved in a direction, as seen from a side of the puzzle.
+    fn rotation(direction: RingDirection, side: ViewingSide) -> Rotation {
+        let rotation = if direction == Self::CLOCKWISE {
+            Rotation::Clockwise
+        } else {
+            Rotation::CounterClockwise
+        };
+        match side {
+            ViewingSide::Hanayama => rotation,
+            ViewingSide::Valve => -rotation,
+        }
+    }
+
+    /// The direction to move the ring in for a rotation as seen from a side of the puzzle.
+    fn direction(rotation: Rotation, side: ViewingSide) -> RingDirection {
+        if Self::rotation(Self::CLOCKWISE, side) == rotation {
+            Self::CLOCKWISE
+        } else {
+            -Self::CLOCKWISE
+        }
+    }
+
     fn position(&self) -> ModPoint<Self::Coordinates>;
     fn points_local(&self) -> &[ModPoint<Self>];
     fn shift(&mut self, vector: Vector2D<i32, Self::Coordinates>);
 
+    /// The top layer of the ring, which is a full section tall.
+    fn top(&self) -> i32 {
+        self.position().y - 1
+    }
+
     fn points(
         &self,
         shift: Option<Vector2D<i32, Self::Coordinates>>,
@@ -204,6 +203,8 @@ pub trait Ring: Sized {
             .map(move |p| (self.position() + p.cast_unit() + shift).into())
     }
 
+    /// Whether the ring can move without overlapping either half ring, with the points of the
+    /// half rings given as they would be after the move.
     fn can_move(
         &self,
         hanayama_points: &HashSet<Point2D<i32, Self::Coordinates>>,
@@ -213,36 +214,103 @@ pub trait Ring: Sized {
         self.points(Some(direction))
             .all(|p| !hanayama_points.contains(&p) && !valve_points.contains(&p))
     }
+
+    /// Where the ring would overlap either half ring after moving, with the points of the
+    /// half rings given as they would be after the move.
+    fn collisions(
+        &self,
+        hanayama_points: &HashSet<Point2D<i32, Self::Coordinates>>,
+        valve_points: &HashSet<Point2D<i32, Self::Coordinates>>,
+        direction: Vector2D<i32, Self::Coordinates>,
+    ) -> Vec<Collision<Self::Coordinates>> {
+        self.points(None)
+            .zip(self.points(Some(direction)))
+            .filter_map(|(ring_point, p)| {
+                let half = if hanayama_points.contains(&p) {
+                    Piece::HanayamaHalf
+                } else if valve_points.contains(&p) {
+                    Piece::ValveHalf
+                } else {
+                    return None;
+                };
+                Some(Collision {
+                    ring_point,
+                    half,
+                    half_point: p,
+                })
+            })
+            .collect()
+    }
+}
+
+/// A point of a ring that would overlap a point of one of the half rings if it moved.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Collision<U> {
+    pub ring_point: Point2D<i32, U>,
+    /// The half ring in the way.
+    pub half: Piece,
+    pub half_point: Point2D<i32, U>,
+}
+
+/// Everything in the way of a move in both sections, which is nothing if it is possible.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct Collisions {
+    pub inside: Vec<Collision<Inside>>,
+    pub outside: Vec<Collision<Outside>>,
+}
+
+/// A state of the model that could not happen with the real pieces.
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+pub enum InvariantError {
+    #[error("the {piece} is displaced by {displacement}, beyond its travel of {} to {}", .travel.start(), .travel.end())]
+    OutOfTravel {
+        piece: Piece,
+        displacement: i32,
+        travel: RangeInclusive<i32>,
+    },
+    #[error("the {piece} overlaps the {other} at ({x}, {y})")]
+    Overlap {
+        piece: Piece,
+        other: Piece,
+        x: i32,
+        y: i32,
+    },
 }
 
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+pub enum MoveError {
+    #[error("{muv} is blocked")]
+    Blocked { muv: String, collisions: Collisions },
+    #[error("{muv} is not possible")]
+    Invariant {
+        muv: String,
+        #[source]
+        source: InvariantError,
+    },
+}
+
+#[derive(Clone)]
 pub struct InnerRing {
     position: ModPoint<Inside>,
 }
 impl Default for InnerRing {
     fn default() -> Self {
         Self {
-            position: ModPoint::new(4, 1),
+            position: Geometry::get().inner_ring.start,
         }
     }
 }
 impl Ring for InnerRing {
     type Coordinates = Inside;
 
+    const CLOCKWISE: RingDirection = RingDirection::Right;
+
     fn position(&self) -> ModPoint<Self::Coordinates> {
         self.position
     }
 
     fn points_local(&self) -> &[ModPoint<Self>] {
-        static INNER_RING_POINTS: LazyLock<[ModPoint<InnerRing>; 4]> = LazyLock::new(|| {
-            [
-                ModPoint::new(0, 1),
-                ModPoint::new(1, 2),
-                ModPoint::new(2, 2),
-                ModPoint::new(3, 2),
-            ]
-        });
-
-        INNER_RING_POINTS.as_ref()
+        &Geometry::get().inner_ring.points
     }
 
     fn shift(&mut self, vector: Vector2D<i32, Self::Coordinates>) {
@@ -250,33 +318,28 @@ impl Ring for InnerRing {
     }
 }
 
+#[derive(Clone)]
 pub struct OuterRing {
     position: ModPoint<Outside>,
 }
 impl Default for OuterRing {
     fn default() -> Self {
         Self {
-            position: ModPoint::new(0, 1),
+            position: Geometry::get().outer_ring.start,
         }
     }
 }
 impl Ring for OuterRing {
     type Coordinates = Outside;
 
+    const CLOCKWISE: RingDirection = RingDirection::Left;
+
     fn position(&self) -> ModPoint<Self::Coordinates> {
         self.position
     }
 
     fn points_local(&self) -> &[ModPoint<Self>] {
-        static OUTER_RING_POINTS: LazyLock<[ModPoint<OuterRing>; 3]> = LazyLock::new(|| {
-            [
-                ModPoint::new(0, 0),
-                ModPoint::new(3, 2),
-                ModPoint::new(5, 2),
-            ]
-        });
-
-        OUTER_RING_POINTS.as_ref()
+        &Geometry::get().outer_ring.points
     }
 
     fn shift(&mut self, vector: Vector2D<i32, Self::Coordinates>) {
@@ -284,7 +347,7 @@ impl Ring for OuterRing {
     }
 }
 
-#[derive(Debug, EnumIter, Clone, Copy)]
+#[derive(Debug, EnumIter, Clone, Copy, PartialEq, Eq)]
 pub enum RingDirection {
     Left,
     Right,
@@ -311,7 +374,50 @@ impl RingDirection {
     }
 }
 
-#[derive(Debug, EnumIter, Clone, Copy)]
+/// The side of the puzzle that it is looked at from to tell which way a ring is rotating.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, ValueEnum)]
+pub enum ViewingSide {
+    /// Looking at the HANAYAMA face, as the guide does.
+    #[default]
+    Hanayama,
+    /// Looking at the opposite face, with the Valve half on top.
+    Valve,
+}
+impl ViewingSide {
+    pub fn name(&self) -> &'static str {
+        match self {
+            ViewingSide::Hanayama => "Hanayama side",
+            ViewingSide::Valve => "Valve side",
+        }
+    }
+}
+
+/// Which way a ring rotates as seen from one side of the puzzle.
+#[derive(Debug, EnumIter, Clone, Copy, PartialEq, Eq)]
+pub enum Rotation {
+    Clockwise,
+    CounterClockwise,
+}
+impl std::ops::Neg for Rotation {
+    type Output = Self;
+
+    fn neg(self) -> Self::Output {
+        match self {
+            Rotation::Clockwise => Self::CounterClockwise,
+            Rotation::CounterClockwise => Self::Clockwise,
+        }
+    }
+}
+impl Rotation {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Rotation::Clockwise => "CW",
+            Rotation::CounterClockwise => "CCW",
+        }
+    }
+}
+
+#[derive(Debug, EnumIter, Clone, Copy, PartialEq, Eq)]
 pub enum VerticalDirection {
     Up,
     Down,
@@ -338,6 +444,29 @@ impl VerticalDirection {
     }
 }
 
+/// Which label on the half rings is facing up while holding the puzzle by the outer ring.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Orientation {
+    HanayamaUp,
+    ValveUp,
+}
+impl Orientation {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Orientation::HanayamaUp => "Hanayama up",
+            Orientation::ValveUp => "Valve up",
+        }
+    }
+
+    /// The direction in which pieces fall.
+    pub fn down(&self) -> VerticalDirection {
+        match self {
+            Orientation::HanayamaUp => VerticalDirection::Down,
+            Orientation::ValveUp => VerticalDirection::Up,
+        }
+    }
+}
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, EnumIter)]
 pub enum VerticalPieces {
     InnerRing,
@@ -345,6 +474,63 @@ pub enum VerticalPieces {
     ValveHalf,
 }
 
+/// Every piece of the puzzle, including the Hanayama half, which never moves.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, EnumIter, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Piece {
+    HanayamaHalf,
+    ValveHalf,
+    InnerRing,
+    OuterRing,
+}
+impl Piece {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Piece::HanayamaHalf => "Hanayama half",
+            Piece::ValveHalf => "Valve half",
+            Piece::InnerRing => "inner ring",
+            Piece::OuterRing => "outer ring",
+        }
+    }
+
+    /// The number of layers that the piece spans, with those of the rings counted from their top.
+    pub fn height(&self) -> i32 {
+        let geometry = Geometry::get();
+        match self {
+            Piece::HanayamaHalf => geometry.hanayama_half.height,
+            Piece::ValveHalf => geometry.valve_half.height,
+            Piece::InnerRing => geometry.inner_ring.height,
+            Piece::OuterRing => geometry.outer_ring.height,
+        }
+    }
+
+    /// How far the piece can be displaced vertically from the Hanayama half while still being
+    /// in contact with it, beyond which the model no longer describes anything physical. The
+    /// piece's top can be as far above the Hanayama half's as it is tall less one layer, and as
+    /// far below as the Hanayama half is tall less one layer.
+    pub fn travel(&self) -> RangeInclusive<i32> {
+        match self {
+            Piece::HanayamaHalf => 0..=0,
+            _ => -(self.height() - 1)..=Piece::HanayamaHalf.height() - 1,
+        }
+    }
+}
+impl std::fmt::Display for Piece {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl From<VerticalPieces> for Piece {
+    fn from(value: VerticalPieces) -> Self {
+        match value {
+            VerticalPieces::InnerRing => Piece::InnerRing,
+            VerticalPieces::OuterRing => Piece::OuterRing,
+            VerticalPieces::ValveHalf => Piece::ValveHalf,
+        }
+    }
+}
+
 pub trait NonEmptyIndexSetExt: Sized {
     fn vertical_piece_iter() -> impl Iterator<Item = Self>;
 
@@ -369,7 +555,7 @@ impl NonEmptyIndexSetExt for NonEmptyIndexSet<VerticalPieces> {
     }
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub enum Move {
     InnerRingRotate(RingDirection),
     OuterRingRotate(RingDirection),
@@ -392,16 +578,45 @@ impl std::ops::Neg for Move {
         }
     }
 }
+/// A single user step made up of moves that are undone and redone together.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Step(pub Vec<Move>);
+impl From<Move> for Step {
+    fn from(value: Move) -> Self {
+        Self(vec![value])
+    }
+}
+impl Edit for Step {
+    type Target = Valve;
+    type Output = ();
+
+    fn edit(&mut self, target: &mut Self::Target) -> Self::Output {
+        for muv in self.0.iter_mut() {
+            muv.edit(target);
+        }
+    }
+
+    fn undo(&mut self, target: &mut Self::Target) -> Self::Output {
+        for muv in self.0.iter_mut().rev() {
+            muv.undo(target);
+        }
+    }
+}
+
 impl Edit for Move {
     type Target = Valve;
     type Output = ();
 
+    // Moves are only recorded once they have been made, so they can always be made again and
+    // taken back, and anything else is a bug rather than something to recover from
     fn edit(&mut self, target: &mut Self::Target) -> Self::Output {
-        target.make_move_unchecked(self);
+        target.make_move(self).expect("recorded moves are possible");
     }
 
     fn undo(&mut self, target: &mut Self::Target) -> Self::Output {
-        target.make_move_unchecked(&-self.clone());
+        target
+            .make_move(&-self.clone())
+            .expect("recorded moves can be taken back");
     }
 }
 impl Move {
@@ -417,6 +632,32 @@ impl Move {
             }))
     }
 
+    /// The pieces that the move moves.
+    pub fn pieces(&self) -> Vec<Piece> {
+        match self {
+            Move::InnerRingRotate(_) => vec![Piece::InnerRing],
+            Move::OuterRingRotate(_) => vec![Piece::OuterRing],
+            Move::Vertical { pieces, .. } => pieces.iter().map(|&vp| vp.into()).collect(),
+        }
+    }
+
+    /// The move in notation, with ring moves written as the rotation seen from a side of the
+    /// puzzle, such as `I-CCW`, if one is given.
+    pub fn name(&self, side: Option<ViewingSide>) -> Cow<'static, str> {
+        let Some(side) = side else {
+            return self.as_str();
+        };
+        match self {
+            Move::InnerRingRotate(d) => {
+                format!("I-{}", InnerRing::rotation(*d, side).as_str()).into()
+            }
+            Move::OuterRingRotate(d) => {
+                format!("O-{}", OuterRing::rotation(*d, side).as_str()).into()
+            }
+            Move::Vertical { .. } => self.as_str(),
+        }
+    }
+
     pub fn as_str(&self) -> Cow<'static, str> {
         match self {
             Move::InnerRingRotate(d) => match d {
@@ -449,20 +690,222 @@ impl Move {
     }
 }
 
+/// How far the sections are drawn from the top of the screen, leaving room for the half rings
+/// to be displaced above them.
 pub const VALVE_MAX_HALF_Y_DISPLACEMENT: i32 = 4;
 pub const VALVE_SECTION_WIDTH: i32 = 6;
 pub const VALVE_SECTION_HEIGHT: i32 = 5;
+/// How far either half ring has to be displaced vertically from the outer ring for the puzzle to
+/// come apart, which leaves only their edge layers level with each other.
+pub const VALVE_SEPARATION_DISPLACEMENT: i32 = VALVE_SECTION_HEIGHT - 1;
 
-#[derive(Default)]
+/// A compact snapshot of the positions of the moving pieces of a [`Valve`].
+///
+/// Since the horizontal ring coordinates are stored modulo the section width, states
+/// whose ring positions differ only by full rotations compare and hash as equal.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct ValveState {
+    pub inner_ring: ModPoint<Inside>,
+    pub outer_ring: ModPoint<Outside>,
+    pub valve_half_y: i32,
+}
+impl Default for ValveState {
+    fn default() -> Self {
+        Valve::default().state()
+    }
+}
+impl std::fmt::Display for ValveState {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "inner ring ({}, {}), outer ring ({}, {}), Valve half {}",
+            self.inner_ring.x.a(),
+            self.inner_ring.y,
+            self.outer_ring.x.a(),
+            self.outer_ring.y,
+            self.valve_half_y
+        )
+    }
+}
+
+#[derive(Default, Clone)]
 pub struct Valve {
     pub inner_ring: InnerRing,
     pub outer_ring: OuterRing,
     pub valve_half: ValveHalf,
     pub hanayama_half: HanayamaHalf,
 }
+impl From<ValveState> for Valve {
+    fn from(value: ValveState) -> Self {
+        let mut valve = Self::default();
+        valve.set_state(value);
+        valve
+    }
+}
 impl Valve {
-    pub fn can_move(&self, muv: &Move) -> bool {
+    pub fn state(&self) -> ValveState {
+        ValveState {
+            inner_ring: self.inner_ring.position,
+            outer_ring: self.outer_ring.position,
+            valve_half_y: self.valve_half.y,
+        }
+    }
+
+    pub fn set_state(&mut self, state: ValveState) {
+        self.inner_ring.position = state.inner_ring;
+        self.outer_ring.position = state.outer_ring;
+        self.valve_half.y = state.valve_half_y;
+    }
+
+    /// Whether the puzzle has come apart.
+    ///
+    /// The half rings are only held together by the outer ring, so this is the case once either
+    /// half is displaced from the outer ring by [`VALVE_SEPARATION_DISPLACEMENT`] or more.
+    /// Separated states are terminal when searching, though the pieces can still be moved back
+    /// together as when reassembling.
+    pub fn is_separated(&self) -> bool {
+        [0, self.valve_half.y]
+            .into_iter()
+            .any(|y| (y - self.outer_ring.top()).abs() >= VALVE_SEPARATION_DISPLACEMENT)
+    }
+
+    /// What is in the way of a move, with every point where it currently is. This is only
+    /// needed to show why a move is blocked, since [`Valve::can_move`] checks it more cheaply.
+    pub fn collisions(&self, muv: &Move) -> Collisions {
+        // The points of the half rings are given as they would be after the move
+        fn unshift<U>(mut collisions: Vec<Collision<U>>, valve_dy: i32) -> Vec<Collision<U>> {
+            for c in collisions.iter_mut().filter(|c| c.half == Piece::ValveHalf) {
+                c.half_point.y -= valve_dy;
+            }
+            collisions
+        }
+
         match muv {
+            Move::InnerRingRotate(dir) => Collisions {
+                inside: self.inner_ring.collisions(
+                    self.hanayama_half.inside_points(),
+                    &self.valve_half.inside_points(None),
+                    dir.vector(),
+                ),
+                outside: Vec::new(),
+            },
+            Move::OuterRingRotate(dir) => Collisions {
+                inside: Vec::new(),
+                outside: self.outer_ring.collisions(
+                    self.hanayama_half.outside_points(),
+                    &self.valve_half.outside_points(None),
+                    dir.vector(),
+                ),
+            },
+            Move::Vertical { direction, pieces } => {
+                let displacements = pieces.vertical_displacements(*direction);
+                let valve_dy = displacements[&VerticalPieces::ValveHalf];
+
+                Collisions {
+                    inside: unshift(
+                        self.inner_ring.collisions(
+                            self.hanayama_half.inside_points(),
+                            &self.valve_half.inside_points(Some(valve_dy)),
+                            Vector2D::new(0, displacements[&VerticalPieces::InnerRing]),
+                        ),
+                        valve_dy,
+                    ),
+                    outside: unshift(
+                        self.outer_ring.collisions(
+                            self.hanayama_half.outside_points(),
+                            &self.valve_half.outside_points(Some(valve_dy)),
+                            Vector2D::new(0, displacements[&VerticalPieces::OuterRing]),
+                        ),
+                        valve_dy,
+                    ),
+                }
+            }
+        }
+    }
+
+    /// How far a piece is displaced vertically from the Hanayama half.
+    pub fn displacement(&self, piece: Piece) -> i32 {
+        match piece {
+            Piece::HanayamaHalf => 0,
+            Piece::ValveHalf => self.valve_half.y,
+            Piece::InnerRing => self.inner_ring.top(),
+            Piece::OuterRing => self.outer_ring.top(),
+        }
+    }
+
+    fn check_travel(&self) -> Result<(), InvariantError> {
+        for piece in Piece::iter() {
+            let displacement = self.displacement(piece);
+            let travel = piece.travel();
+            if !travel.contains(&displacement) {
+                return Err(InvariantError::OutOfTravel {
+                    piece,
+                    displacement,
+                    travel,
+                });
+            }
+        }
+        Ok(())
+    }
+
+    /// Ensures that the state could happen with the real pieces, which is the case after any
+    /// possible move.
+    pub fn check_invariants(&self) -> Result<(), InvariantError> {
+        fn check<U>(pieces: [(Piece, HashSet<Point2D<i32, U>>); 3]) -> Result<(), InvariantError> {
+            for (i, (piece, points)) in pieces.iter().enumerate() {
+                for (other, other_points) in pieces.iter().skip(i + 1) {
+                    if let Some(p) = points.intersection(other_points).next() {
+                        return Err(InvariantError::Overlap {
+                            piece: *piece,
+                            other: *other,
+                            x: p.x,
+                            y: p.y,
+                        });
+                    }
+                }
+            }
+            Ok(())
+        }
+
+        self.check_travel()?;
+        check::<Inside>([
+            (
+                Piece::HanayamaHalf,
+                self.hanayama_half.inside_points().clone(),
+            ),
+            (Piece::ValveHalf, self.valve_half.inside_points(None)),
+            (Piece::InnerRing, self.inner_ring.points(None).collect()),
+        ])?;
+        check::<Outside>([
+            (
+                Piece::HanayamaHalf,
+                self.hanayama_half.outside_points().clone(),
+            ),
+            (Piece::ValveHalf, self.valve_half.outside_points(None)),
+            (Piece::OuterRing, self.outer_ring.points(None).collect()),
+        ])
+    }
+
+    /// Whether a move is possible, which is when nothing is in the way and no piece is taken
+    /// beyond its travel.
+    ///
+    /// This includes moves once the puzzle has come apart, which put it back together, so
+    /// anything searching for a way apart has to treat separated states as terminal itself, as
+    /// [`StateGraph::explore`](crate::graph::StateGraph::explore) does.
+    pub fn can_move(&self, muv: &Move) -> bool {
+        if self.is_blocked(muv) {
+            return false;
+        }
+
+        let mut next = self.clone();
+        next.make_move_unchecked(muv);
+        next.check_travel().is_ok()
+    }
+
+    /// Whether anything is in the way of a move, without working out what it is as
+    /// [`Valve::collisions`] does.
+    pub fn is_blocked(&self, muv: &Move) -> bool {
+        let unblocked = match muv {
             Move::InnerRingRotate(dir) => self.inner_ring.can_move(
                 self.hanayama_half.inside_points(),
                 &self.valve_half.inside_points(None),
@@ -487,7 +930,108 @@ impl Valve {
                     Vector2D::new(0, displacements[&VerticalPieces::OuterRing]),
                 )
             }
+        };
+        !unblocked
+    }
+
+    /// Makes a move if it is possible, checking that the result could happen with the real
+    /// pieces, and otherwise leaves the valve as it is.
+    pub fn make_move(&mut self, muv: &Move) -> Result<(), MoveError> {
+        if self.is_blocked(muv) {
+            return Err(MoveError::Blocked {
+                muv: muv.as_str().into_owned(),
+                collisions: self.collisions(muv),
+            });
         }
+
+        let mut next = self.clone();
+        next.make_move_unchecked(muv);
+        next.check_invariants()
+            .map_err(|source| MoveError::Invariant {
+                muv: muv.as_str().into_owned(),
+                source,
+            })?;
+
+        *self = next;
+        Ok(())
+    }
+
+    /// The move by which unsupported pieces fall one layer while the outer ring is held.
+    ///
+    /// Since the Hanayama half is fixed in the model, it falls by moving everything else
+    /// the other way. Whenever several sets of pieces could fall, the largest one does.
+    pub fn fall(&self, orientation: Orientation) -> Option<Move> {
+        let down = orientation.down();
+
+        NonEmptyIndexSet::vertical_piece_iter()
+            .filter_map(|pieces| {
+                let held = pieces.contains(&VerticalPieces::OuterRing);
+                let falling = if held {
+                    // The Hanayama half along with any pieces left behind
+                    1 + VerticalPieces::iter()
+                        .filter(|vp| !pieces.contains(vp))
+                        .count()
+                } else {
+                    pieces.len()
+                };
+                let muv = Move::Vertical {
+                    direction: if held { -down } else { down },
+                    pieces,
+                };
+
+                self.can_move(&muv).then_some((falling, muv))
+            })
+            .max_by_key(|(falling, _)| *falling)
+            .map(|(_, muv)| muv)
+    }
+
+    /// The moves by which unsupported pieces fall as far as they can while the outer ring is held.
+    pub fn falls(&self, orientation: Orientation) -> Vec<Move> {
+        let mut valve = self.clone();
+        let mut moves = Vec::new();
+
+        // Nothing can fall further than the height of a section before coming apart
+        for _ in 0..VALVE_SECTION_HEIGHT {
+            match valve.fall(orientation) {
+                Some(muv) => {
+                    valve.make_move_unchecked(&muv);
+                    moves.push(muv);
+                }
+                None => break,
+            }
+        }
+
+        moves
+    }
+
+    /// The moves of making a ring rotation up to `segments` times, stopping early if it is
+    /// blocked, with unsupported pieces falling after every segment if there is gravity.
+    ///
+    /// If the next segment would bring the valve back to where it started, as a full turn of a
+    /// ring that turns freely does, it stops there instead, which leaves nothing to make.
+    pub fn rotations(&self, muv: &Move, segments: i32, gravity: Option<Orientation>) -> Vec<Move> {
+        let mut valve = self.clone();
+        let mut moves = Vec::new();
+        for _ in 0..segments {
+            if !valve.can_move(muv) {
+                break;
+            }
+            valve.make_move_unchecked(muv);
+            moves.push(muv.clone());
+
+            if let Some(orientation) = gravity {
+                for fall in valve.falls(orientation) {
+                    valve.make_move_unchecked(&fall);
+                    moves.push(fall);
+                }
+            }
+
+            if valve.state() == self.state() {
+                return Vec::new();
+            }
+        }
+
+        moves
     }
 
     pub fn make_move_unchecked(&mut self, muv: &Move) {
@@ -513,3 +1057,36 @@ impl Valve {
         }
     }
 }
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rotations_stop_when_blocked_or_back_where_they_started() {
+        let valve = Valve::default();
+
+        // The inner ring turns left until it stops, but not right at all
+        let left = Move::InnerRingRotate(RingDirection::Left);
+        let moves = valve.rotations(&left, VALVE_SECTION_WIDTH, None);
+        assert!(!moves.is_empty() && moves.len() < VALVE_SECTION_WIDTH as usize);
+        assert!(moves.iter().all(|m| *m == left));
+        let mut stopped = valve.clone();
+        for muv in moves.iter() {
+            stopped.make_move(muv).unwrap();
+        }
+        assert!(!stopped.can_move(&left));
+
+        let right = Move::InnerRingRotate(RingDirection::Right);
+        assert!(!valve.can_move(&right));
+        assert_eq!(valve.rotations(&right, VALVE_SECTION_WIDTH, None), []);
+
+        // The outer ring turns freely, so a full turn is no rotation at all, but anything short
+        // of one is made
+        let outer = Move::OuterRingRotate(RingDirection::Left);
+        assert_eq!(valve.rotations(&outer, VALVE_SECTION_WIDTH, None), []);
+        assert_eq!(
+            valve.rotations(&outer, VALVE_SECTION_WIDTH - 1, None).len(),
+            VALVE_SECTION_WIDTH as usize - 1
+        );
+    }
+}